@@ -1,3 +1,4 @@
 # Deposits one NEAR into the account every 1 hour
+# Transfer tasks send the "deposit" directly, no contract method is needed to receive it
 
-near call cron.$NEAR_ACCT create_task '{"contract_id": "counter.'$NEAR_ACCT'","function_id": "increment","cadence": "0 * * * *","recurring": true,"deposit": "0","gas": 2400000000000}' --accountId counter.$NEAR_ACCT --amount 1
\ No newline at end of file
+near call cron.$NEAR_ACCT create_task '{"contract_id": "counter.'$NEAR_ACCT'","cadence": "0 0 * * * *","recurring": true,"deposit": "1000000000000000000000000","options": {"kind": "Transfer"}}' --accountId counter.$NEAR_ACCT --amount 25
@@ -5,7 +5,6 @@ use near_sdk::{
     json_types::{Base64VecU8, U128},
     log, near_bindgen,
     serde::{Deserialize, Serialize},
-    serde_json,
     AccountId, BorshStorageKey, Gas, PanicOnDefault, Promise,
 };
 
@@ -45,15 +44,14 @@ pub struct Task {
     pub total_deposit: U128,
     pub deposit: U128,
     pub gas: Gas,
-    pub arguments: Vec<u8>,
+    pub arguments: Base64VecU8,
 }
 
 #[ext_contract(ext_croncat)]
 pub trait ExtCroncat {
     fn get_tasks(&self, offset: Option<u64>) -> (Vec<Base64VecU8>, U128);
     fn get_all_tasks(&self, slot: Option<U128>) -> Vec<Task>;
-    // fn get_task(&self, task_hash: Base64VecU8) -> Task;
-    fn get_task(&self, task_hash: String) -> Task;
+    fn get_task(&self, task_hash: Base64VecU8) -> Task;
     fn create_task(
         &mut self,
         contract_id: String,
@@ -359,17 +357,13 @@ impl CrudContract {
     /// near call crosscontract.testnet status
     /// ```
     pub fn status(&self) -> Promise {
-        // TODO: fix this! serialization is not working
-        let hash = self.task_hash.clone().expect(ERR_NO_TASK_CONFIGURED);
-        log!("TASK HASH: {:?} {:?} {}", &hash, serde_json::to_string(&hash).unwrap(), serde_json::to_string(&hash).unwrap());
         ext_croncat::get_task(
-            // hash,
-            serde_json::to_string(&hash).unwrap().to_string(),
+            self.task_hash.clone().expect(ERR_NO_TASK_CONFIGURED),
             &self.cron.clone().expect(ERR_NO_CRON_CONFIGURED),
             NO_DEPOSIT,
             GAS_FOR_STATUS_CALL,
         )
-        .then(ext::schedule_callback(
+        .then(ext::status_callback(
             &env::current_account_id(),
             NO_DEPOSIT,
             GAS_FOR_STATUS_CALLBACK,
@@ -5,7 +5,6 @@ use near_sdk::{
     json_types::{Base64VecU8, U128},
     log, near_bindgen,
     serde::{Deserialize, Serialize},
-    serde_json,
     AccountId, BorshStorageKey, Gas, PanicOnDefault, Promise,
 };
 
@@ -22,17 +21,23 @@ pub const MILLISECONDS_IN_DAY: u64 = 86_400_000;
 pub const NO_DEPOSIT: u128 = 0;
 pub const GAS_FOR_TICK_CALL: Gas = 7_000_000_000_000;
 pub const GAS_FOR_SCHEDULE_CALL: Gas = 25_000_000_000_000;
-pub const GAS_FOR_SCHEDULE_CALLBACK: Gas = 5_000_000_000_000;
+pub const GAS_FOR_SCHEDULE_CALLBACK: Gas = 20_000_000_000_000;
 pub const GAS_FOR_UPDATE_CALL: Gas = 15_000_000_000_000;
 pub const GAS_FOR_REMOVE_CALL: Gas = 20_000_000_000_000;
 pub const GAS_FOR_STATUS_CALL: Gas = 25_000_000_000_000;
 pub const GAS_FOR_STATUS_CALLBACK: Gas = 25_000_000_000_000;
+pub const GAS_FOR_NOTIFY_CALL: Gas = 10_000_000_000_000;
+pub const GAS_FOR_REFILL_CALL: Gas = 10_000_000_000_000;
+pub const REFILL_AMOUNT: u128 = ONE_NEAR;
+pub const REFILL_RESERVE: u128 = 5 * ONE_NEAR; // Balance this contract keeps, refills never dip into it
+pub const REFILL_INTERVAL: u64 = MILLISECONDS_IN_HOUR * NANOS; // Min time between refills
 
 /// Error messages
 const ERR_ONLY_OWNER: &str = "Must be called by owner";
 const ERR_NO_CRON_CONFIGURED: &str = "No cron account configured, cannot schedule";
 const ERR_NO_TASK_CONFIGURED: &str =
     "No task hash found, need to schedule a cron task to set and get it.";
+const ERR_ONLY_CRON: &str = "Must be called by the cron account";
 
 #[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(crate = "near_sdk::serde")]
@@ -45,15 +50,14 @@ pub struct Task {
     pub total_deposit: U128,
     pub deposit: U128,
     pub gas: Gas,
-    pub arguments: Vec<u8>,
+    pub arguments: Base64VecU8,
 }
 
 #[ext_contract(ext_croncat)]
 pub trait ExtCroncat {
     fn get_tasks(&self, offset: Option<u64>) -> (Vec<Base64VecU8>, U128);
     fn get_all_tasks(&self, slot: Option<U128>) -> Vec<Task>;
-    // fn get_task(&self, task_hash: Base64VecU8) -> Task;
-    fn get_task(&self, task_hash: String) -> Task;
+    fn get_task(&self, task_hash: Base64VecU8) -> Task;
     fn create_task(
         &mut self,
         contract_id: String,
@@ -74,6 +78,14 @@ pub trait ExtCroncat {
         arguments: Option<Vec<u8>>,
     );
     fn remove_task(&mut self, task_hash: Base64VecU8);
+    fn refill_task(&mut self, task_hash: Base64VecU8);
+    fn set_balance_notify(
+        &mut self,
+        task_hash: Base64VecU8,
+        contract_id: Option<AccountId>,
+        function_id: Option<String>,
+        threshold: Option<u64>,
+    );
     fn proxy_call(&mut self);
 }
 
@@ -127,6 +139,8 @@ pub struct CrudContract {
     task_hash: Option<Base64VecU8>,
     // Cron account
     cron: Option<AccountId>,
+    // Timestamp of the last task refill
+    last_refill: u64,
 }
 
 #[near_bindgen]
@@ -150,6 +164,7 @@ impl CrudContract {
             daily: Vector::new(StorageKeys::DailySeries),
             task_hash: None,
             cron,
+            last_refill: 0,
         }
     }
 
@@ -295,10 +310,46 @@ impl CrudContract {
     }
 
     /// Get the task hash, and store in state
+    /// Then ask croncat to call "refill" once the task runs low on balance
     #[private]
-    pub fn schedule_callback(&mut self, #[callback] task_hash: Base64VecU8) {
+    pub fn schedule_callback(&mut self, #[callback] task_hash: Base64VecU8) -> Promise {
         log!("schedule_callback task_hash {:?}", &task_hash);
-        self.task_hash = Some(task_hash);
+        self.task_hash = Some(task_hash.clone());
+
+        ext_croncat::set_balance_notify(
+            task_hash,
+            Some(env::current_account_id()),
+            Some("refill".to_string()),
+            None,
+            &self.cron.clone().expect(ERR_NO_CRON_CONFIGURED),
+            NO_DEPOSIT,
+            GAS_FOR_NOTIFY_CALL,
+        )
+    }
+
+    /// Tops up the task balance, called by croncat when the task runs low on balance
+    /// NOTE: Pays from this contract's own balance, at most once per `REFILL_INTERVAL`
+    /// & never below `REFILL_RESERVE`, logic could be added to decide how much to refill.
+    pub fn refill(&mut self, task_hash: Base64VecU8, balance: U128) -> Promise {
+        let cron = self.cron.clone().expect(ERR_NO_CRON_CONFIGURED);
+        assert_eq!(env::predecessor_account_id(), cron, "{}", ERR_ONLY_CRON);
+        assert_eq!(
+            Some(&task_hash),
+            self.task_hash.as_ref(),
+            "Only this contract's task is refilled"
+        );
+        assert!(
+            env::block_timestamp() >= self.last_refill + REFILL_INTERVAL,
+            "Task refilled too recently"
+        );
+        assert!(
+            env::account_balance() >= REFILL_AMOUNT + REFILL_RESERVE,
+            "Not enough balance left to refill the task"
+        );
+        log!("Task balance low at {}, refilling", balance.0);
+        self.last_refill = env::block_timestamp();
+
+        ext_croncat::refill_task(task_hash, &cron, REFILL_AMOUNT, GAS_FOR_REFILL_CALL)
     }
 
     /// Update a scheduled task using a known task hash, passing new updateable parameters. MUST be owner!
@@ -359,17 +410,13 @@ impl CrudContract {
     /// near call crosscontract.testnet status
     /// ```
     pub fn status(&self) -> Promise {
-        // TODO: fix this! serialization is not working
-        let hash = self.task_hash.clone().expect(ERR_NO_TASK_CONFIGURED);
-        log!("TASK HASH: {:?} {:?} {}", &hash, serde_json::to_string(&hash).unwrap(), serde_json::to_string(&hash).unwrap());
         ext_croncat::get_task(
-            // hash,
-            serde_json::to_string(&hash).unwrap().to_string(),
+            self.task_hash.clone().expect(ERR_NO_TASK_CONFIGURED),
             &self.cron.clone().expect(ERR_NO_CRON_CONFIGURED),
             NO_DEPOSIT,
             GAS_FOR_STATUS_CALL,
         )
-        .then(ext::schedule_callback(
+        .then(ext::status_callback(
             &env::current_account_id(),
             NO_DEPOSIT,
             GAS_FOR_STATUS_CALLBACK,
@@ -0,0 +1,110 @@
+//! Minimal stand-in for the core staking pool contract, used by the manager simulation tests.
+//!
+//! Implements the subset of the staking pool interface the manager relies on:
+//! [deposit_and_stake], [unstake], [withdraw_all] and the account balance views.
+//! Unstaked balances are available for withdrawal immediately, there is no epoch delay.
+//!
+//! [deposit_and_stake]: struct.StakingPool.html#method.deposit_and_stake
+//! [unstake]: struct.StakingPool.html#method.unstake
+//! [withdraw_all]: struct.StakingPool.html#method.withdraw_all
+
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::LookupMap,
+    env,
+    json_types::{ValidAccountId, U128},
+    log, near_bindgen, AccountId, Balance, BorshStorageKey, PanicOnDefault, Promise,
+};
+
+near_sdk::setup_alloc!();
+
+#[derive(BorshStorageKey, BorshSerialize)]
+pub enum StorageKeys {
+    Accounts,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Account {
+    pub staked: Balance,
+    pub unstaked: Balance,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct StakingPool {
+    accounts: LookupMap<AccountId, Account>,
+}
+
+#[near_bindgen]
+impl StakingPool {
+    /// ```bash
+    /// near call pool.testnet new --accountId pool.testnet
+    /// ```
+    #[init]
+    pub fn new() -> Self {
+        StakingPool {
+            accounts: LookupMap::new(StorageKeys::Accounts),
+        }
+    }
+
+    /// Stakes the attached deposit for the predecessor account
+    #[payable]
+    pub fn deposit_and_stake(&mut self) {
+        let account_id = env::predecessor_account_id();
+        let amount = env::attached_deposit();
+        let mut account = self.accounts.get(&account_id).unwrap_or_default();
+        account.staked += amount;
+        self.accounts.insert(&account_id, &account);
+        log!("@{} deposited and staked {}", account_id, amount);
+    }
+
+    /// Moves part of the staked balance into the unstaked balance
+    pub fn unstake(&mut self, amount: U128) {
+        let account_id = env::predecessor_account_id();
+        let mut account = self.accounts.get(&account_id).unwrap_or_default();
+        assert!(
+            account.staked >= amount.0,
+            "Not enough staked balance to unstake"
+        );
+        account.staked -= amount.0;
+        account.unstaked += amount.0;
+        self.accounts.insert(&account_id, &account);
+        log!("@{} unstaked {}", account_id, amount.0);
+    }
+
+    /// Transfers the entire unstaked balance back to the predecessor account
+    pub fn withdraw_all(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let mut account = self.accounts.get(&account_id).unwrap_or_default();
+        let amount = account.unstaked;
+        assert!(amount > 0, "No unstaked balance to withdraw");
+        account.unstaked = 0;
+        self.accounts.insert(&account_id, &account);
+        log!("@{} withdrawing {}", account_id, amount);
+        Promise::new(account_id).transfer(amount)
+    }
+
+    /// Mock only: the attached deposit is paid out as staking rewards to `account_id`
+    #[payable]
+    pub fn add_reward(&mut self, account_id: ValidAccountId) {
+        let account_id: AccountId = account_id.into();
+        let amount = env::attached_deposit();
+        let mut account = self.accounts.get(&account_id).unwrap_or_default();
+        account.staked += amount;
+        self.accounts.insert(&account_id, &account);
+        log!("@{} earned {} in rewards", account_id, amount);
+    }
+
+    pub fn get_account_staked_balance(&self, account_id: ValidAccountId) -> U128 {
+        U128::from(self.accounts.get(account_id.as_ref()).unwrap_or_default().staked)
+    }
+
+    pub fn get_account_unstaked_balance(&self, account_id: ValidAccountId) -> U128 {
+        U128::from(self.accounts.get(account_id.as_ref()).unwrap_or_default().unstaked)
+    }
+
+    pub fn get_account_total_balance(&self, account_id: ValidAccountId) -> U128 {
+        let account = self.accounts.get(account_id.as_ref()).unwrap_or_default();
+        U128::from(account.staked + account.unstaked)
+    }
+}
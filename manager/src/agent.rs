@@ -1,5 +1,3 @@
-use near_contract_standards::storage_management::StorageManagement;
-
 use crate::*;
 
 #[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq)]
@@ -15,6 +13,7 @@ impl Contract {
 
     /// Add any account as an agent that will be able to execute tasks.
     /// Registering allows for rewards accruing with micro-payments which will accumulate to more long-term.
+    /// NOTE: Registering with `storage_deposit` is deprecated, it covers task owner storage when given `registration_only`.
     ///
     /// Optional Parameters:
     /// "payable_account_id" - Allows a different account id to be specified, so a user can receive funds at a different account than the agent account.
@@ -40,6 +39,8 @@ impl Contract {
         );
 
         let account = env::predecessor_account_id();
+        self.assert_agent_whitelisted(&account);
+
         // check that account isn't already added
         if let Some(agent) = self.agents.get(&account) {
             let panic_msg = format!("Agent already exists: {:?}. Refunding the deposit.", agent);
@@ -97,8 +98,9 @@ impl Contract {
     /// ```
     #[payable]
     pub fn unregister_agent(&mut self) {
-        // This method name is quite explicit, so calling storage_unregister and setting the 'force' option to true.
-        self.storage_unregister(Some(true));
+        // This method name is quite explicit, so removing the agent with the 'force' option set to true.
+        assert_one_yocto();
+        self.internal_unregister_agent(&env::predecessor_account_id(), true);
     }
 
     /// Allows an agent to withdraw all rewards, paid to the specified payable account id.
@@ -137,6 +139,19 @@ impl Contract {
     }
 }
 
+// Internal methods
+impl Contract {
+    /// Only whitelisted agents can register & execute tasks while whitelist mode is enabled
+    pub(crate) fn assert_agent_whitelisted(&self, account_id: &AccountId) {
+        if self.agent_whitelist_enabled {
+            assert!(
+                self.agent_whitelist.contains(account_id),
+                "Agent not whitelisted"
+            );
+        }
+    }
+}
+
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use super::*;
@@ -247,6 +262,21 @@ mod tests {
         contract.withdraw_task_balance();
     }
 
+    #[test]
+    #[should_panic(expected = "Agent not whitelisted")]
+    fn test_agent_register_not_whitelisted() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            agent_whitelist_enabled: Some(true),
+            ..Default::default()
+        });
+        contract.add_agent_whitelist(accounts(2));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        contract.register_agent(None);
+    }
+
     #[test]
     fn agent_storage_check() {
         let context = get_context(accounts(1));
@@ -0,0 +1,281 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Adds to the credit balance of `account_id` (defaults to the caller).
+    /// Tasks the account owns or sponsors draw their execution costs from it once their own balance runs out,
+    /// up to the task's `credit_cap`. The storage of a new credit balance is paid from the deposit.
+    ///
+    /// ```bash
+    /// near call cron.testnet deposit_credit '{"account_id": "YOU.testnet"}' --accountId YOU.testnet --amount 10
+    /// ```
+    #[payable]
+    pub fn deposit_credit(&mut self, account_id: Option<ValidAccountId>) -> U128 {
+        let amount = env::attached_deposit();
+        let account_id: AccountId = account_id
+            .map(|a| a.into())
+            .unwrap_or_else(|| env::predecessor_account_id());
+
+        let initial_storage_usage = env::storage_usage();
+        let balance = self.credit_balances.get(&account_id).unwrap_or(0);
+        self.credit_balances.insert(&account_id, &balance);
+        let storage_cost = Balance::from(env::storage_usage() - initial_storage_usage)
+            * env::storage_byte_cost();
+        assert!(
+            amount > storage_cost,
+            "The attached deposit doesn't cover the credit balance storage of {}",
+            storage_cost
+        );
+
+        let balance = balance + amount - storage_cost;
+        self.credit_balances.insert(&account_id, &balance);
+        log_event(
+            "credit_deposit",
+            json!({ "account_id": account_id, "amount": U128::from(amount), "balance": U128::from(balance) }),
+        );
+        U128::from(balance)
+    }
+
+    /// Withdraws `amount` (defaults to all) of the caller's credit balance.
+    /// Withdrawing all closes the credit balance, refunding its storage as well.
+    /// Requires attaching 1 yoctoⓃ ensure it comes from a full-access key.
+    ///
+    /// ```bash
+    /// near call cron.testnet withdraw_credit '{"amount": "1000000000000000000000000"}' --accountId YOU.testnet --depositYocto 1
+    /// ```
+    #[payable]
+    pub fn withdraw_credit(&mut self, amount: Option<U128>) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let balance = self
+            .credit_balances
+            .get(&account_id)
+            .expect("No credit balance found");
+        let amount = amount.map(|a| a.0).unwrap_or(balance);
+        assert!(
+            amount <= balance,
+            "Not enough credit balance, available {}",
+            balance
+        );
+
+        let mut refund = amount;
+        if amount == balance {
+            let initial_storage_usage = env::storage_usage();
+            self.credit_balances.remove(&account_id);
+            refund += Balance::from(initial_storage_usage - env::storage_usage())
+                * env::storage_byte_cost();
+        } else {
+            self.credit_balances.insert(&account_id, &(balance - amount));
+        }
+        log_event(
+            "credit_withdraw",
+            json!({ "account_id": account_id, "amount": U128::from(refund) }),
+        );
+        Promise::new(account_id).transfer(refund)
+    }
+
+    /// Gets the credit balance of an account
+    ///
+    /// ```bash
+    /// near view cron.testnet get_credit_balance '{"account_id": "YOU.testnet"}'
+    /// ```
+    pub fn get_credit_balance(&self, account_id: ValidAccountId) -> U128 {
+        U128::from(self.credit_balances.get(account_id.as_ref()).unwrap_or(0))
+    }
+}
+
+// Internal methods
+impl Contract {
+    /// Credit the task can still draw from its payer's credit balance, limited by its `credit_cap`
+    /// The payer is the sponsor of the task if set, otherwise the owner
+    pub(crate) fn task_credit_available(&self, task: &Task) -> Balance {
+        let balance = self.credit_balances.get(task.payer_id()).unwrap_or(0);
+        match task.credit_cap {
+            Some(cap) => core::cmp::min(balance, cap.0.saturating_sub(task.credit_used.0)),
+            None => balance,
+        }
+    }
+
+    /// Moves `amount` from the payer's credit balance into the task balance
+    /// Nothing is drawn if the available credit doesn't cover all of it
+    /// NOTE: Settle the task yield first, the task deposit changes
+    pub(crate) fn draw_task_credit(&mut self, task: &mut Task, amount: Balance) -> bool {
+        if amount > self.task_credit_available(task) {
+            log!("Not enough credit available for the task");
+            return false;
+        }
+
+        let payer_id = task.payer_id().clone();
+        let balance = self.credit_balances.get(&payer_id).unwrap_or(0);
+        self.credit_balances.insert(&payer_id, &(balance - amount));
+        task.credit_used = U128::from(task.credit_used.0 + amount);
+        task.total_deposit = U128::from(task.total_deposit.0 + amount);
+        self.total_task_deposits += amount;
+        true
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    const BLOCK_START_BLOCK: u64 = 52_201_040;
+    const BLOCK_START_TS: u64 = 1_624_151_503_447_000_000;
+    const CALL_BALANCE: u128 = AGENT_BASE_FEE + 200 * GAS_BASE_PRICE;
+
+    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .signer_account_pk(b"ed25519:4ZhGmuKTfQn9ZpHCQVRwEr4JnutL8Uu3kArfxEqksfVM".to_vec())
+            .predecessor_account_id(predecessor_account_id)
+            .block_index(BLOCK_START_BLOCK)
+            .block_timestamp(BLOCK_START_TS);
+        builder
+    }
+
+    fn create_credit_task(
+        contract: &mut Contract,
+        recurring: bool,
+        credit_cap: Option<U128>,
+    ) -> TaskHash {
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(recurring),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                credit_cap,
+                ..Default::default()
+            }),
+        )
+    }
+
+    #[test]
+    fn test_credit_deposit_withdraw() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        let balance = contract.deposit_credit(None).0;
+        assert!(balance > 0 && balance < ONE_NEAR, "Storage is paid from the deposit");
+        let other_balance = contract.deposit_credit(Some(accounts(2))).0;
+        assert_eq!(contract.get_credit_balance(accounts(2)).0, other_balance);
+
+        // Deposits add up, storage is only paid once
+        contract.deposit_credit(None);
+        assert_eq!(contract.get_credit_balance(accounts(1)).0, balance + ONE_NEAR);
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw_credit(Some(U128::from(ONE_NEAR)));
+        assert_eq!(contract.get_credit_balance(accounts(1)).0, balance);
+        contract.withdraw_credit(None);
+        assert_eq!(contract.get_credit_balance(accounts(1)).0, 0);
+        assert!(contract.credit_balances.get(&accounts(1).to_string()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough credit balance")]
+    fn test_credit_withdraw_too_much() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.deposit_credit(None);
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw_credit(Some(U128::from(ONE_NEAR)));
+    }
+
+    #[test]
+    fn test_credit_funds_task() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, Some(false));
+        let credit = contract.deposit_credit(None).0;
+
+        // Nothing attached, executions are paid from the credit balance
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = create_credit_task(&mut contract, false, None);
+        assert_eq!(contract.get_task(task_hash.clone()).total_deposit.0, 0);
+
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        assert!(contract.tasks.get(&task_hash).is_none());
+        assert_eq!(contract.get_credit_balance(accounts(1)).0, credit - CALL_BALANCE);
+    }
+
+    #[test]
+    fn test_credit_cap() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, Some(false));
+        let credit = contract.deposit_credit(None).0;
+
+        // The task balance covers the first run, the credit cap the second
+        testing_env!(context
+            .attached_deposit(CALL_BALANCE)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = create_credit_task(&mut contract, true, Some(U128::from(CALL_BALANCE)));
+
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        assert_eq!(contract.get_credit_balance(accounts(1)).0, credit);
+        let mut context = get_context(accounts(0));
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (75 * NANO))
+            .block_index(BLOCK_START_BLOCK + 75)
+            .build());
+        contract.callback_for_proxy_call(task_hash.clone(), U128::from(52201080));
+
+        let mut context = get_context(accounts(1));
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (125 * NANO))
+            .block_index(BLOCK_START_BLOCK + 125)
+            .build());
+        contract.proxy_call();
+        assert_eq!(contract.get_credit_balance(accounts(1)).0, credit - CALL_BALANCE);
+        assert!(contract.tasks.get(&task_hash).is_none(), "Task exits once the cap is used");
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough task balance to execute job")]
+    fn test_credit_cap_not_enuf() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, Some(false));
+        contract.deposit_credit(None);
+
+        testing_env!(context.attached_deposit(0).build());
+        create_credit_task(&mut contract, false, Some(U128::from(CALL_BALANCE - 1)));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::*;
+use near_sdk::serde_json::Value;
+
+pub const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+pub const EVENT_STANDARD: &str = "croncat";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+/// Logs a state change as a NEP-297 event, so indexers can parse it:
+/// `EVENT_JSON:{"standard":"croncat","version":"1.0.0","event":"...","data":{...}}`
+pub(crate) fn log_event(event: &str, data: Value) {
+    let event = json!({
+        "standard": EVENT_STANDARD,
+        "version": EVENT_VERSION,
+        "event": event,
+        "data": data,
+    });
+    log!("{}{}", EVENT_JSON_PREFIX, event);
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    const BLOCK_START_BLOCK: u64 = 52_201_040;
+    const BLOCK_START_TS: u64 = 1_624_151_503_447_000_000;
+
+    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .signer_account_pk(b"ed25519:4ZhGmuKTfQn9ZpHCQVRwEr4JnutL8Uu3kArfxEqksfVM".to_vec())
+            .predecessor_account_id(predecessor_account_id)
+            .block_index(BLOCK_START_BLOCK)
+            .block_timestamp(BLOCK_START_TS);
+        builder
+    }
+
+    #[test]
+    fn test_log_event() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.add_agent_whitelist(accounts(2));
+
+        let logs = get_logs();
+        let event = logs.last().expect("No event logged");
+        assert!(event.starts_with(EVENT_JSON_PREFIX));
+        let event: Value =
+            near_sdk::serde_json::from_str(&event[EVENT_JSON_PREFIX.len()..]).unwrap();
+        assert_eq!(event["standard"], EVENT_STANDARD);
+        assert_eq!(event["version"], EVENT_VERSION);
+        assert_eq!(event["event"], "agent_whitelist_add");
+        assert_eq!(event["data"]["account_id"], accounts(2).to_string());
+    }
+}
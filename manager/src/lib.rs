@@ -1,24 +1,36 @@
 use cron_schedule::Schedule;
 use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
-    collections::{LookupMap, TreeMap, UnorderedMap},
+    collections::{LookupMap, TreeMap, UnorderedMap, UnorderedSet},
     env,
     json_types::{Base64VecU8, ValidAccountId, U128, U64},
     log, near_bindgen,
     serde::{Deserialize, Serialize},
     serde_json::json,
-    AccountId, Balance, BorshStorageKey, Gas, PanicOnDefault, Promise,
+    AccountId, Balance, BorshStorageKey, Gas, PanicOnDefault, Promise, PromiseResult,
     StorageUsage,
     assert_one_yocto,
 };
 use std::str::FromStr;
 pub use agent::Agent;
-pub use tasks::Task;
+pub use staking::{StakingAction, YieldDistribution};
+pub use tasks::{
+    ActionKind, BalanceNotify, CatchUpPolicy, ResultCallback, SlotMode, SlotOrdering, Task,
+    TaskAction, TaskHash, TaskOptions,
+};
+pub use treasury::TreasuryInfo;
+use events::log_event;
+use owner::SettingsUpdate;
+use storage_impl::StorageAccount;
 
 mod agent;
+mod credits;
+mod events;
 mod owner;
+mod staking;
 mod storage_impl;
 mod tasks;
+mod treasury;
 mod utils;
 mod views;
 
@@ -28,24 +40,87 @@ near_sdk::setup_alloc!();
 pub const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
 pub const GAS_BASE_PRICE: Balance = 100_000_000;
 pub const GAS_BASE_FEE: Gas = 3_000_000_000_000;
-// actual is: 13534954161128, higher in case treemap rebalance
+// actual is: 13534954161128, higher in case a new slot rebalances the slot treemap
 pub const GAS_FOR_CALLBACK: Gas = 30_000_000_000_000;
+pub const GAS_FOR_PROXY_CALL: Gas = 30_000_000_000_000; // `proxy_call` itself, on the same terms as the callback
+pub const MAX_GAS: Gas = 300_000_000_000_000; // Max prepaid gas of a transaction
+pub const GAS_FOR_TICK: Gas = 150_000_000_000_000;
+pub const GAS_FOR_STAKING: Gas = 75_000_000_000_000;
+pub const GAS_FOR_STAKING_CALLBACK: Gas = 10_000_000_000_000;
+pub const STAKING_UNLOCK_EPOCHS: u64 = 4; // Epochs the staking pool keeps unstaked funds locked
+pub const GAS_FOR_BALANCE_NOTIFY: Gas = 25_000_000_000_000; // Receiver & a follow-up call like `refill_task`
+pub const GAS_FOR_ON_RESULT: Gas = 10_000_000_000_000;
+pub const GAS_FOR_TRANSFER: Gas = 500_000_000_000; // Covers the transfer action & receipt fees
 pub const AGENT_BASE_FEE: Balance = 1_000_000_000_000_000_000_000; // 0.001 Ⓝ
+pub const AGENT_FEE_BACKLOG: u64 = 10; // Slots behind before the max agent fee applies
 pub const STAKE_BALANCE_MIN: u128 = 10 * ONE_NEAR;
+pub const TREASURY_FEE_DENOMINATOR: u64 = 10_000; // Fee share in basis points
+pub const TREASURY_WITHDRAW_CAP: Balance = 100 * ONE_NEAR;
+pub const TREASURY_WITHDRAW_PERIOD: u64 = 86_400 * NANO; // 1 day
+pub const GAS_FOR_TREASURY_CALLBACK: Gas = 10_000_000_000_000;
+pub const LIQUIDITY_RESERVE_DENOMINATOR: u64 = 10_000; // Reserve ratio in basis points
+pub const LIQUIDITY_RESERVE_RATIO: u64 = 5_000;
+pub const YIELD_PRECISION: u128 = 1_000_000_000_000; // Scales yield per deposit accumulator
 
 // Boundary Definitions
 pub const MAX_BLOCK_RANGE: u64 = 1_000_000_000_000_000;
 pub const MAX_EPOCH_RANGE: u32 = 10_000;
 pub const MAX_SECOND_RANGE: u32 = 600_000_000;
-pub const SLOT_GRANULARITY: u64 = 60; // NOTE: Connection drain.. might be required if slot granularity changes
+pub const SLOT_GRANULARITY: u64 = 60; // NOTE: Changes re-slot all tasks, see `reslot_tasks`
+pub const SLOT_CAPACITY: u64 = 0; // Max task hashes per slot, 0 is unlimited
+pub const MAX_SLOT_OVERFLOW: u64 = 100; // Max following slots checked for capacity
+pub const MAX_ARGUMENTS_SIZE: u64 = 4_096; // Max bytes of task arguments
+pub const MAX_TASK_ACTIONS: u64 = 5; // Max following actions per task, each needs its own callback gas
+pub const BALANCE_NOTIFY_THRESHOLD: u64 = 3; // Default executions left before notifying the task owner
+pub const MAX_CATCH_UP_RUNS: u64 = 100; // Max missed runs counted, or caught up, per task execution
+pub const RESLOT_LIMIT: u64 = 100; // Default number of tasks moved per `reslot_tasks` call
+pub const TICK_CADENCE: &str = "0 0 * * * *"; // Hourly
+pub const SLOT_JITTER: u64 = 0; // Default window in blocks tasks are spread over, 0 is disabled
 pub const NANO: u64 = 1_000_000_000;
 pub const BPS_DENOMINATOR: u64 = 1_000;
+pub const BPS_DEFAULT: u64 = 1_000; // Assume 1 block per second until samples are available
+pub const BPS_SAMPLE_SIZE: u64 = 12; // Block & timestamp samples kept for the blocks per second estimate
+pub const BPS_SAMPLE_INTERVAL: u64 = 600; // Min blocks between samples taken during `proxy_call`
+pub const BPS_TICK_INTERVAL: u64 = 10; // Min blocks between samples taken during `tick`
 
 #[derive(BorshStorageKey, BorshSerialize)]
 pub enum StorageKeys {
     Tasks,
     Agents,
     Slots,
+    AgentWhitelist,
+    SlotEntries,
+    SlotKeys,
+    StorageAccounts,
+    CreditBalances,
+}
+
+/// Queue of the task hashes sharing an order key within a slot, see `slot_entries`
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SlotLane {
+    key: u128,
+    head: u64,
+    tail: u64,
+    len: u64,
+}
+
+/// Task hashes of a single slot, one lane per order key sorted by key
+/// Only the lane indexes are stored here, the hashes are in `slot_entries`.
+/// NOTE: With `SlotOrdering::Fifo` a slot has a single lane, so pushing & popping costs the same
+/// however many tasks it holds. Other orderings cost a lane per distinct key in the slot.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct SlotQueue {
+    lanes: Vec<SlotLane>,
+}
+
+impl SlotQueue {
+    pub fn len(&self) -> u64 {
+        self.lanes.iter().map(|lane| lane.len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lanes.is_empty()
+    }
 }
 
 #[near_bindgen]
@@ -54,24 +129,65 @@ pub struct Contract {
     // Runtime
     paused: bool,
     owner_id: AccountId,
-    bps_block: [u64; 2],
-    bps_timestamp: [u64; 2],
+    bps_samples: Vec<(u64, u64)>,
+    bps_sample_index: u64,
+    tick_task_hash: TaskHash,
 
     // Basic management
     agents: LookupMap<AccountId, Agent>,
-    slots: TreeMap<u128, Vec<Vec<u8>>>,
-    tasks: UnorderedMap<Vec<u8>, Task>,
+    slots: TreeMap<u128, SlotQueue>,
+    slot_entries: LookupMap<(u128, u128, u64), TaskHash>,
+    slot_keys: LookupMap<TaskHash, (u128, u64)>,
+    slot_ordering: SlotOrdering,
+    slot_mode: SlotMode,
+    reslot_cursor: Option<u64>,
+    tasks: UnorderedMap<TaskHash, Task>,
+    agent_whitelist: UnorderedSet<AccountId>,
+    agent_whitelist_enabled: bool,
 
     // Economics
     available_balance: Balance,
     staked_balance: Balance,
     agent_fee: Balance,
+    agent_fee_max: Balance,
+    agent_fee_backlog: u64,
     gas_price: Balance,
     proxy_callback_gas: Gas,
     slot_granularity: u64,
+    slot_capacity: u64,
+    slot_jitter: u64,
+
+    // Treasury
+    tick_balance: Balance,
+    treasury_fee: u64,
+    treasury_account_id: Option<AccountId>,
+    treasury_withdraw_cap: Balance,
+    treasury_withdraw_period: u64,
+    treasury_period_start: u64,
+    treasury_period_withdrawn: Balance,
+    treasury_total_collected: Balance,
+    treasury_total_withdrawn: Balance,
+
+    // Staking
+    staking_pool_id: Option<AccountId>,
+    liquidity_reserve_ratio: u64,
+    unstaked_balance: Balance,
+    unstake_unlock_epoch: u64,
+    staking_in_progress: bool,
+    staking_pending: Option<(StakingAction, Balance)>,
+    yield_distribution: YieldDistribution,
+    yield_per_deposit: u128,
+    total_task_deposits: Balance,
+    agent_reward_pool: Balance,
+    total_yield: Balance,
 
     // Storage
     agent_storage_usage: StorageUsage,
+    max_arguments_size: u64,
+    storage_accounts: LookupMap<AccountId, StorageAccount>,
+
+    // Credits
+    credit_balances: LookupMap<AccountId, Balance>,
 }
 
 #[near_bindgen]
@@ -84,20 +200,56 @@ impl Contract {
         let mut this = Contract {
             paused: false,
             owner_id: env::signer_account_id(),
-            bps_block: [env::block_index(), env::block_index()],
-            bps_timestamp: [env::block_timestamp(), env::block_timestamp()],
+            bps_samples: vec![(env::block_index(), env::block_timestamp())],
+            bps_sample_index: 1,
+            tick_task_hash: TaskHash::default(),
             tasks: UnorderedMap::new(StorageKeys::Tasks),
             agents: LookupMap::new(StorageKeys::Agents),
             slots: TreeMap::new(StorageKeys::Slots),
+            slot_entries: LookupMap::new(StorageKeys::SlotEntries),
+            slot_keys: LookupMap::new(StorageKeys::SlotKeys),
+            slot_ordering: SlotOrdering::Priority,
+            slot_mode: SlotMode::Block,
+            reslot_cursor: None,
+            agent_whitelist: UnorderedSet::new(StorageKeys::AgentWhitelist),
+            agent_whitelist_enabled: false,
             available_balance: 0,
             staked_balance: 0,
             agent_fee: AGENT_BASE_FEE,
+            agent_fee_max: AGENT_BASE_FEE,
+            agent_fee_backlog: AGENT_FEE_BACKLOG,
             gas_price: GAS_BASE_PRICE,
             proxy_callback_gas: GAS_FOR_CALLBACK,
             slot_granularity: SLOT_GRANULARITY,
+            slot_capacity: SLOT_CAPACITY,
+            slot_jitter: SLOT_JITTER,
+            tick_balance: 0,
+            treasury_fee: 0,
+            treasury_account_id: None,
+            treasury_withdraw_cap: TREASURY_WITHDRAW_CAP,
+            treasury_withdraw_period: TREASURY_WITHDRAW_PERIOD,
+            treasury_period_start: env::block_timestamp(),
+            treasury_period_withdrawn: 0,
+            treasury_total_collected: 0,
+            treasury_total_withdrawn: 0,
+            staking_pool_id: None,
+            liquidity_reserve_ratio: LIQUIDITY_RESERVE_RATIO,
+            unstaked_balance: 0,
+            unstake_unlock_epoch: 0,
+            staking_in_progress: false,
+            staking_pending: None,
+            yield_distribution: YieldDistribution::Tasks,
+            yield_per_deposit: 0,
+            total_task_deposits: 0,
+            agent_reward_pool: 0,
+            total_yield: 0,
             agent_storage_usage: 0,
+            max_arguments_size: MAX_ARGUMENTS_SIZE,
+            storage_accounts: LookupMap::new(StorageKeys::StorageAccounts),
+            credit_balances: LookupMap::new(StorageKeys::CreditBalances),
         };
         this.measure_account_storage_usage();
+        this.schedule_tick_task(TICK_CADENCE.to_string());
         this
     }
 
@@ -117,74 +269,178 @@ impl Contract {
         self.agents.remove(&tmp_account_id);
     }
 
+    /// Width of a slot, blocks or nanoseconds depending on `slot_mode`
+    fn slot_width(&self) -> u128 {
+        let granularity = u128::from(core::cmp::max(self.slot_granularity, 1));
+        match self.slot_mode {
+            SlotMode::Block => granularity,
+            SlotMode::Timestamp => granularity * u128::from(NANO),
+        }
+    }
+
     /// Takes an optional `offset`: the number of blocks to offset from now (current block height)
     /// If no offset, returns current slot based on current block height
     /// If offset, returns next slot based on current block height & integer offset
     /// rounded to nearest granularity (~every 1.6 block per sec)
+    /// NOTE: With `SlotMode::Timestamp`, slots & offset use the block timestamp in nanoseconds instead
     fn get_slot_id(&self, offset: Option<u64>) -> u128 {
-        let current_block = env::block_index();
+        let width = self.slot_width() as u64;
+        let current = match self.slot_mode {
+            SlotMode::Block => env::block_index(),
+            SlotMode::Timestamp => env::block_timestamp(),
+        };
         let slot_id: u64 = if let Some(o) = offset {
             // NOTE: Assumption here is that the offset will be in seconds. (blocks per second)
             //       Slot granularity will be in minutes (60 blocks per slot)
 
-            let slot_remainder = core::cmp::max(o % self.slot_granularity, 1);
-            let slot_round =
-                core::cmp::max(o.saturating_sub(slot_remainder), self.slot_granularity);
-            let next = current_block + slot_round;
+            let slot_remainder = core::cmp::max(o % width, 1);
+            let slot_round = core::cmp::max(o.saturating_sub(slot_remainder), width);
+            let next = current + slot_round;
 
             // Protect against extreme future block schedules
-            if next - current_block > current_block + MAX_BLOCK_RANGE {
-                u64::min(next, current_block + MAX_BLOCK_RANGE)
+            if self.slot_mode == SlotMode::Block && next - current > current + MAX_BLOCK_RANGE {
+                u64::min(next, current + MAX_BLOCK_RANGE)
             } else {
                 next
             }
         } else {
-            current_block
+            current
         };
 
-        let slot_remainder = slot_id % self.slot_granularity;
+        let slot_remainder = slot_id % width;
         let slot_id_round = slot_id.saturating_sub(slot_remainder);
 
         u128::from(slot_id_round)
     }
 
-    /// Parse cadence into a schedule
-    /// Get next approximate block from a schedule
-    /// return slot from the difference of upcoming block and current block
-    fn get_slot_from_cadence(&self, cadence: String) -> u128 {
-        let current_block = env::block_index();
-        let current_block_ts = env::block_timestamp();
+    /// Records the current block & timestamp in the `bps_samples` ring buffer,
+    /// when at least `min_blocks` passed since the latest sample
+    /// Returns whether a sample was recorded
+    fn record_bps_sample(&mut self, min_blocks: u64) -> bool {
+        let latest_block = self.bps_samples.iter().map(|(block, _)| *block).max().unwrap_or(0);
+        if latest_block + min_blocks >= env::block_index() {
+            return false;
+        }
+
+        let sample = (env::block_index(), env::block_timestamp());
+        let index = (self.bps_sample_index % BPS_SAMPLE_SIZE) as usize;
+        if index < self.bps_samples.len() {
+            self.bps_samples[index] = sample;
+        } else {
+            self.bps_samples.push(sample);
+        }
+        self.bps_sample_index += 1;
+        true
+    }
+
+    /// Estimated blocks per second, multiplied by `BPS_DENOMINATOR`
+    /// Averages the intervals between consecutive samples & the interval since the latest sample,
+    /// ignoring intervals more than 2x off the median rate (halted chain, clock jumps, etc)
+    fn get_bps(&self) -> u64 {
+        let mut samples = self.bps_samples.clone();
+        samples.push((env::block_index(), env::block_timestamp()));
+        samples.sort();
 
-        // Schedule params
-        // NOTE: eventually use TryFrom
-        let schedule = Schedule::from_str(&cadence).unwrap();
-        let next_ts = schedule.next_after(&current_block_ts).unwrap();
-        let next_diff = next_ts - current_block_ts;
-
-        // calculate the average blocks, to get predicted future block
-        // Get the range of blocks for which we're taking the average
-        // Remember `bps_block` is updated after every call to `tick`
-        let blocks_total = core::cmp::max(current_block - self.bps_block[1], 1);
         // Generally, avoiding floats can be useful, here we set a denominator
         // Since the `bps` timestamp is in nanoseconds, we multiply the
         // numerator to match the magnitude
-        // We use the `max` value to avoid division by 0
-        let bps = ((blocks_total * NANO * BPS_DENOMINATOR)
-            / std::cmp::max(current_block_ts - self.bps_timestamp[1], 1)).max(1);
-
-        /*
-        seconds * nano      blocks           1
-         ---             *  ---         *   ---   = blocks offset (with extra 1000 magnitude)
-          1             seconds * 1000      1000
-        */
-        let offset =
-            ((next_diff as u128 * bps as u128) / BPS_DENOMINATOR as u128 / NANO as u128) as u64;
+        let intervals: Vec<(u128, u128)> = samples
+            .windows(2)
+            .filter(|w| w[1].0 > w[0].0 && w[1].1 > w[0].1)
+            .map(|w| (u128::from(w[1].0 - w[0].0), u128::from(w[1].1 - w[0].1)))
+            .collect();
+        let rate = |(blocks, ns): &(u128, u128)| {
+            blocks * NANO as u128 * BPS_DENOMINATOR as u128 / ns
+        };
+
+        if intervals.is_empty() {
+            // No usable interval yet, right after deploy or when called in the same block
+            return BPS_DEFAULT;
+        }
+
+        let mut rates: Vec<u128> = intervals.iter().map(rate).collect();
+        rates.sort();
+        let median = rates[rates.len() / 2];
+        let (blocks, ns) = intervals
+            .iter()
+            .filter(|i| {
+                let r = rate(*i);
+                r * 2 >= median && r <= median * 2
+            })
+            .fold((0, 0), |(b, t), (blocks, ns)| (b + blocks, t + ns));
+
+        ((blocks * NANO as u128 * BPS_DENOMINATOR as u128 / ns) as u64).max(1)
+    }
+
+    /// Estimates the block timestamp of a slot from the current block & the bps estimate
+    /// NOTE: With `SlotMode::Timestamp`, slots already are timestamps
+    fn get_slot_timestamp(&self, slot: u128) -> u64 {
+        if self.slot_mode == SlotMode::Timestamp {
+            return slot as u64;
+        }
+        let current_block = u128::from(env::block_index());
+        let current_block_ts = env::block_timestamp();
+        let bps = u128::from(self.get_bps());
+        if slot >= current_block {
+            let diff = (slot - current_block) * NANO as u128 * BPS_DENOMINATOR as u128 / bps;
+            current_block_ts.saturating_add(diff as u64)
+        } else {
+            let diff = (current_block - slot) * NANO as u128 * BPS_DENOMINATOR as u128 / bps;
+            current_block_ts.saturating_sub(diff as u64)
+        }
+    }
+
+    /// Returns the cadence occurrences after `from_ts` that are already due, up to `MAX_CATCH_UP_RUNS`
+    /// These are the runs missed when a task executes after its following occurrence
+    fn get_missed_runs(&self, cadence: &String, from_ts: u64) -> Vec<u64> {
+        let current_block_ts = env::block_timestamp();
+        let schedule = Schedule::from_str(cadence).unwrap();
+        let mut ts = from_ts;
+        let mut missed = vec![];
+        while (missed.len() as u64) < MAX_CATCH_UP_RUNS {
+            match schedule.next_after(&ts) {
+                Some(next_ts) if next_ts <= current_block_ts => {
+                    missed.push(next_ts);
+                    ts = next_ts;
+                }
+                _ => break,
+            }
+        }
+        missed
+    }
+
+    /// Parse cadence into a schedule
+    /// Get the next fire timestamp after `from_ts`
+    fn get_next_timestamp(&self, cadence: &String, from_ts: u64) -> u64 {
+        // NOTE: eventually use TryFrom
+        let schedule = Schedule::from_str(cadence).unwrap();
+        schedule.next_after(&from_ts).unwrap()
+    }
+
+    /// Get next approximate block from a fire timestamp
+    /// return slot from the difference of upcoming block and current block
+    fn get_slot_from_timestamp(&self, next_ts: u64) -> u128 {
+        let next_diff = next_ts.saturating_sub(env::block_timestamp());
+
+        let offset = match self.slot_mode {
+            SlotMode::Block => {
+                let bps = self.get_bps();
+
+                /*
+                seconds * nano      blocks           1
+                 ---             *  ---         *   ---   = blocks offset (with extra 1000 magnitude)
+                  1             seconds * 1000      1000
+                */
+                ((next_diff as u128 * bps as u128) / BPS_DENOMINATOR as u128 / NANO as u128) as u64
+            }
+            SlotMode::Timestamp => next_diff,
+        };
         let current = self.get_slot_id(None);
         let next_slot = self.get_slot_id(Some(offset));
 
         if current == next_slot {
             // Add slot granularity to make sure the minimum next slot is a block within next slot granularity range
-            current + u128::from(self.slot_granularity)
+            current + self.slot_width()
         } else {
             next_slot
         }
@@ -1,9 +1,48 @@
 use crate::*;
 
+/// Settings changed by `update_settings`, fields left out keep their current value
+#[derive(Default)]
+pub(crate) struct SettingsUpdate {
+    pub owner_id: Option<AccountId>,
+    pub slot_granularity: Option<u64>,
+    pub paused: Option<bool>,
+    pub agent_fee: Option<U128>,
+    pub gas_price: Option<U128>,
+    pub proxy_callback_gas: Option<U64>,
+    pub agent_whitelist_enabled: Option<bool>,
+    pub treasury_fee: Option<u64>,
+    pub treasury_account_id: Option<ValidAccountId>,
+    pub treasury_withdraw_cap: Option<U128>,
+    pub treasury_withdraw_period: Option<U64>,
+    pub staking_pool_id: Option<ValidAccountId>,
+    pub liquidity_reserve_ratio: Option<u64>,
+    pub yield_distribution: Option<YieldDistribution>,
+    pub agent_fee_max: Option<U128>,
+    pub agent_fee_backlog: Option<u64>,
+    pub slot_ordering: Option<SlotOrdering>,
+    pub slot_capacity: Option<u64>,
+    pub slot_jitter: Option<u64>,
+    pub slot_mode: Option<SlotMode>,
+    pub tick_cadence: Option<String>,
+    pub max_arguments_size: Option<u64>,
+}
+
 #[near_bindgen]
 impl Contract {
     /// Changes core configurations
     /// Should only be updated by owner -- in best case DAO based :)
+    ///
+    /// NOTE: `agent_fee` is the fee paid while slots are executed on time, the fee rises up to
+    /// `agent_fee_max` as slots fall `agent_fee_backlog` slots behind the current slot.
+    ///
+    /// NOTE: Changing `slot_mode`, `slot_granularity` or `slot_ordering` pauses task execution, creation & removal
+    /// until every task was moved with `reslot_tasks`.
+    /// With `SlotMode::Timestamp`, `slot_granularity` & `slot_jitter` are in seconds instead of blocks.
+    ///
+    /// ```bash
+    /// near call cron.testnet update_settings '{"agent_fee": "1000000000000000000000", "paused": false}' --accountId cron.testnet
+    /// ```
+    #[allow(clippy::too_many_arguments)]
     pub fn update_settings(
         &mut self,
         owner_id: Option<AccountId>,
@@ -12,16 +51,150 @@ impl Contract {
         agent_fee: Option<U128>,
         gas_price: Option<U128>,
         proxy_callback_gas: Option<U64>,
+        agent_whitelist_enabled: Option<bool>,
+        treasury_fee: Option<u64>,
+        treasury_account_id: Option<ValidAccountId>,
+        treasury_withdraw_cap: Option<U128>,
+        treasury_withdraw_period: Option<U64>,
+        staking_pool_id: Option<ValidAccountId>,
+        liquidity_reserve_ratio: Option<u64>,
+        yield_distribution: Option<YieldDistribution>,
+        agent_fee_max: Option<U128>,
+        agent_fee_backlog: Option<u64>,
+        slot_ordering: Option<SlotOrdering>,
+        slot_capacity: Option<u64>,
+        slot_jitter: Option<u64>,
+        slot_mode: Option<SlotMode>,
+        tick_cadence: Option<String>,
+        max_arguments_size: Option<u64>,
     ) {
+        self.apply_settings(SettingsUpdate {
+            owner_id,
+            slot_granularity,
+            paused,
+            agent_fee,
+            gas_price,
+            proxy_callback_gas,
+            agent_whitelist_enabled,
+            treasury_fee,
+            treasury_account_id,
+            treasury_withdraw_cap,
+            treasury_withdraw_period,
+            staking_pool_id,
+            liquidity_reserve_ratio,
+            yield_distribution,
+            agent_fee_max,
+            agent_fee_backlog,
+            slot_ordering,
+            slot_capacity,
+            slot_jitter,
+            slot_mode,
+            tick_cadence,
+            max_arguments_size,
+        });
+    }
+
+    /// Moves pending tasks into the slots matching the current slot settings, in batches of `limit` tasks.
+    /// Task execution stays paused until every task was moved.
+    ///
+    /// ```bash
+    /// near call cron.testnet reslot_tasks '{"limit": 100}' --accountId cron.testnet
+    /// ```
+    pub fn reslot_tasks(&mut self, limit: Option<u64>) {
         assert_eq!(self.owner_id, env::predecessor_account_id(), "Must be owner");
+        let start = self.reslot_cursor.expect("No re-slot in progress");
+        let end = core::cmp::min(start + limit.unwrap_or(RESLOT_LIMIT), self.tasks.len());
+
+        let task_hashes = self.tasks.keys_as_vector();
+        for index in start..end {
+            let task_hash = task_hashes.get(index).expect("No task found by index");
+            self.reslot_task(&task_hash);
+        }
+
+        if end >= self.tasks.len() {
+            self.reslot_cursor = None;
+            log_event("reslot_complete", json!({ "moved": end }));
+        } else {
+            self.reslot_cursor = Some(end);
+            log_event("reslot_progress", json!({ "moved": end, "total": self.tasks.len() }));
+        }
+    }
+
+    /// Allows an agent account to register & execute tasks while whitelist mode is enabled
+    ///
+    /// ```bash
+    /// near call cron.testnet add_agent_whitelist '{"account_id": "YOUR_AGENT.testnet"}' --accountId cron.testnet
+    /// ```
+    pub fn add_agent_whitelist(&mut self, account_id: ValidAccountId) {
+        assert_eq!(self.owner_id, env::predecessor_account_id(), "Must be owner");
+        let account_id: AccountId = account_id.into();
+        if self.agent_whitelist.insert(&account_id) {
+            log_event("agent_whitelist_add", json!({ "account_id": account_id }));
+        }
+    }
+
+    /// Removes an agent account from the whitelist
+    /// NOTE: Does not unregister the agent, but a removed agent can no longer execute tasks while whitelist mode is enabled
+    ///
+    /// ```bash
+    /// near call cron.testnet remove_agent_whitelist '{"account_id": "YOUR_AGENT.testnet"}' --accountId cron.testnet
+    /// ```
+    pub fn remove_agent_whitelist(&mut self, account_id: ValidAccountId) {
+        assert_eq!(self.owner_id, env::predecessor_account_id(), "Must be owner");
+        let account_id: AccountId = account_id.into();
+        if self.agent_whitelist.remove(&account_id) {
+            log_event("agent_whitelist_remove", json!({ "account_id": account_id }));
+        }
+    }
+}
+
+// Internal methods
+impl Contract {
+    /// Applies the settings of `update_settings`, fields left out keep their current value
+    pub(crate) fn apply_settings(&mut self, settings: SettingsUpdate) {
+        assert_eq!(self.owner_id, env::predecessor_account_id(), "Must be owner");
+        let SettingsUpdate {
+            owner_id,
+            slot_granularity,
+            paused,
+            agent_fee,
+            gas_price,
+            proxy_callback_gas,
+            agent_whitelist_enabled,
+            treasury_fee,
+            treasury_account_id,
+            treasury_withdraw_cap,
+            treasury_withdraw_period,
+            staking_pool_id,
+            liquidity_reserve_ratio,
+            yield_distribution,
+            agent_fee_max,
+            agent_fee_backlog,
+            slot_ordering,
+            slot_capacity,
+            slot_jitter,
+            slot_mode,
+            tick_cadence,
+            max_arguments_size,
+        } = settings;
 
         // BE CAREFUL!
         if let Some(owner_id) = owner_id {
             self.owner_id = owner_id;
         }
+        // Before any re-slot starts, the catalog can't change while re-slotting
+        if let Some(tick_cadence) = tick_cadence {
+            assert!(self.validate_cadence(&tick_cadence), "Cadence string invalid");
+            self.schedule_tick_task(tick_cadence);
+        }
 
         if let Some(slot_granularity) = slot_granularity {
-            self.slot_granularity = slot_granularity;
+            assert!(slot_granularity > 0, "Slot granularity must be above 0");
+            if slot_granularity != self.slot_granularity {
+                self.slot_granularity = slot_granularity;
+                self.reslot_cursor = Some(0);
+                log_event("reslot_start", json!({ "slot_granularity": slot_granularity }));
+            }
         }
         if let Some(paused) = paused {
             self.paused = paused;
@@ -35,6 +208,74 @@ impl Contract {
         if let Some(proxy_callback_gas) = proxy_callback_gas {
             self.proxy_callback_gas = proxy_callback_gas.0;
         }
+        if let Some(agent_whitelist_enabled) = agent_whitelist_enabled {
+            self.agent_whitelist_enabled = agent_whitelist_enabled;
+        }
+        if let Some(treasury_fee) = treasury_fee {
+            assert!(
+                treasury_fee <= TREASURY_FEE_DENOMINATOR,
+                "Treasury fee cannot exceed {}",
+                TREASURY_FEE_DENOMINATOR
+            );
+            self.treasury_fee = treasury_fee;
+        }
+        if let Some(treasury_account_id) = treasury_account_id {
+            self.treasury_account_id = Some(treasury_account_id.into());
+        }
+        if let Some(treasury_withdraw_cap) = treasury_withdraw_cap {
+            self.treasury_withdraw_cap = treasury_withdraw_cap.0;
+        }
+        if let Some(treasury_withdraw_period) = treasury_withdraw_period {
+            self.treasury_withdraw_period = treasury_withdraw_period.0;
+        }
+        if let Some(staking_pool_id) = staking_pool_id {
+            // Funds delegated to the previous pool would be lost track of
+            assert!(
+                self.staked_balance == 0 && self.unstaked_balance == 0,
+                "Must unstake & withdraw from current staking pool first"
+            );
+            self.staking_pool_id = Some(staking_pool_id.into());
+        }
+        if let Some(liquidity_reserve_ratio) = liquidity_reserve_ratio {
+            assert!(
+                liquidity_reserve_ratio <= LIQUIDITY_RESERVE_DENOMINATOR,
+                "Liquidity reserve ratio cannot exceed {}",
+                LIQUIDITY_RESERVE_DENOMINATOR
+            );
+            self.liquidity_reserve_ratio = liquidity_reserve_ratio;
+        }
+        if let Some(yield_distribution) = yield_distribution {
+            self.yield_distribution = yield_distribution;
+        }
+        if let Some(agent_fee_max) = agent_fee_max {
+            self.agent_fee_max = agent_fee_max.0;
+        }
+        if let Some(agent_fee_backlog) = agent_fee_backlog {
+            self.agent_fee_backlog = agent_fee_backlog;
+        }
+        if let Some(slot_ordering) = slot_ordering {
+            if slot_ordering != self.slot_ordering {
+                self.slot_ordering = slot_ordering;
+                self.reslot_cursor = Some(0);
+                log_event("reslot_start", json!({ "slot_ordering": slot_ordering }));
+            }
+        }
+        if let Some(slot_capacity) = slot_capacity {
+            self.slot_capacity = slot_capacity;
+        }
+        if let Some(slot_jitter) = slot_jitter {
+            self.slot_jitter = slot_jitter;
+        }
+        if let Some(max_arguments_size) = max_arguments_size {
+            self.max_arguments_size = max_arguments_size;
+        }
+        if let Some(slot_mode) = slot_mode {
+            if slot_mode != self.slot_mode {
+                self.slot_mode = slot_mode;
+                self.reslot_cursor = Some(0);
+                log_event("reslot_start", json!({ "slot_mode": slot_mode }));
+            }
+        }
     }
 }
 
@@ -76,7 +317,10 @@ mod tests {
             .signer_account_id(accounts(3))
             .predecessor_account_id(accounts(3))
             .build());
-        contract.update_settings(None, Some(10), None, None, None, None);
+        contract.update_settings(
+            None, Some(10), None, None, None, None, None, None, None, None, None, None, None,
+            None, None, None, None, None, None, None, None, None,
+        );
     }
 
     #[test]
@@ -88,9 +332,50 @@ mod tests {
         assert_eq!(contract.slot_granularity, SLOT_GRANULARITY);
 
         testing_env!(context.is_view(false).build());
-        contract.update_settings(None, Some(10), Some(true), None, None, None);
+        contract.update_settings(
+            None, Some(10), Some(true), None, None, None, None, None, None, None, None, None, None,
+            None, None, None, None, None, None, None, None, None,
+        );
         testing_env!(context.is_view(true).build());
         assert_eq!(contract.slot_granularity, 10);
         assert_eq!(contract.paused, true);
     }
+
+    #[test]
+    fn test_agent_whitelist() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            agent_whitelist_enabled: Some(true),
+            ..Default::default()
+        });
+        contract.add_agent_whitelist(accounts(2));
+        contract.add_agent_whitelist(accounts(3));
+        testing_env!(context.is_view(true).build());
+        assert_eq!(contract.agent_whitelist_enabled, true);
+        assert_eq!(
+            contract.get_agent_whitelist(None, None),
+            vec![accounts(2).to_string(), accounts(3).to_string()]
+        );
+
+        testing_env!(context.is_view(false).build());
+        contract.remove_agent_whitelist(accounts(2));
+        testing_env!(context.is_view(true).build());
+        assert_eq!(contract.is_agent_whitelisted(accounts(2)), false);
+        assert_eq!(contract.is_agent_whitelisted(accounts(3)), true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Must be owner")]
+    fn test_agent_whitelist_not_owner() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context
+            .signer_account_id(accounts(3))
+            .predecessor_account_id(accounts(3))
+            .build());
+        contract.add_agent_whitelist(accounts(3));
+    }
 }
\ No newline at end of file
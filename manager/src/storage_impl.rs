@@ -1,51 +1,192 @@
-use crate::Contract;
+use crate::{log_event, Contract};
 use near_contract_standards::storage_management::{
     StorageBalance, StorageBalanceBounds, StorageManagement,
 };
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::json_types::{ValidAccountId, U128};
-use near_sdk::{assert_one_yocto, env, log, AccountId, Balance, Promise};
+use near_sdk::serde_json::json;
+use near_sdk::{assert_one_yocto, env, log, near_bindgen, AccountId, Balance, Promise};
+
+/// Storage balance of a task owner, task storage is reserved from it before the attached deposit
+/// `used` covers the account registration & the storage reserved by the owner's tasks
+#[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq)]
+pub struct StorageAccount {
+    pub total: Balance,
+    pub used: Balance,
+}
 
 impl Contract {
     fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
-        if self.agents.contains_key(account_id) {
-            // The "available" balance is always zero because the storage isn't
-            // variable for this contract.
-            Some(StorageBalance {
-                total: self.storage_balance_bounds().min,
-                available: 0.into(),
-            })
+        // The "available" balance of an agent is always zero because the agent storage isn't
+        // variable for this contract.
+        let agent_total = if self.agents.contains_key(account_id) {
+            Some(self.storage_balance_bounds().min.0)
         } else {
             None
+        };
+        let owner_account = self.storage_accounts.get(account_id);
+        if agent_total.is_none() && owner_account.is_none() {
+            return None;
+        }
+
+        let (owner_total, owner_available) = owner_account
+            .map(|a| (a.total, a.total - a.used))
+            .unwrap_or((0, 0));
+        Some(StorageBalance {
+            total: (agent_total.unwrap_or(0) + owner_total).into(),
+            available: owner_available.into(),
+        })
+    }
+
+    /// Reserves task storage from the owner's storage balance
+    /// Returns false if the owner has no storage balance covering the amount
+    pub(crate) fn reserve_task_storage(&mut self, owner_id: &AccountId, amount: Balance) -> bool {
+        match self.storage_accounts.get(owner_id) {
+            Some(mut account) if account.total - account.used >= amount => {
+                account.used += amount;
+                self.storage_accounts.insert(owner_id, &account);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Makes task storage reserved by `reserve_task_storage` available again
+    pub(crate) fn release_task_storage(&mut self, owner_id: &AccountId, amount: Balance) {
+        if let Some(mut account) = self.storage_accounts.get(owner_id) {
+            account.used = account.used.saturating_sub(amount);
+            self.storage_accounts.insert(owner_id, &account);
+        }
+    }
+
+    /// Removes the agent, refunding its balance. The agent must have no balance, unless forced.
+    pub(crate) fn internal_unregister_agent(&mut self, account_id: &AccountId, force: bool) -> bool {
+        if let Some(agent) = self.agents.get(account_id) {
+            let balance = agent.balance.0;
+            if balance == 0 || force {
+                self.agents.remove(account_id);
+                // We add 1 to reimburse for the 1 yoctoⓃ used to call this method
+                Promise::new(account_id.clone()).transfer(balance + 1);
+                log!(
+                    "Agent has been removed and refunded the storage cost of {}",
+                    balance + 1
+                );
+                true
+            } else {
+                env::panic(b"Can't unregister the agent with the positive balance. Must use the 'force' parameter if desired.")
+            }
+        } else {
+            log!("The agent {} is not registered", account_id);
+            false
+        }
+    }
+
+    /// Closes the storage account of a task owner, refunding its balance.
+    /// Storage reserved by tasks can't be forced out, the tasks need to be removed first.
+    /// `refund_yocto` adds the 1 yoctoⓃ used to call this method, unless another refund covers it.
+    fn internal_unregister_task_owner(&mut self, account_id: &AccountId, refund_yocto: bool) -> bool {
+        if let Some(account) = self.storage_accounts.get(account_id) {
+            assert!(
+                account.used <= self.storage_balance_bounds().min.0,
+                "Can't unregister while tasks reserve storage, remove the tasks first"
+            );
+            self.storage_accounts.remove(account_id);
+            let refund = account.total + u128::from(refund_yocto);
+            Promise::new(account_id.clone()).transfer(refund);
+            log_event(
+                "storage_unregister",
+                json!({ "account_id": account_id, "amount": U128::from(refund) }),
+            );
+            true
+        } else {
+            false
         }
     }
 }
 
+#[near_bindgen]
 impl StorageManagement for Contract {
-    // `registration_only` doesn't affect the implementation here, as there's no need to add additional
-    // storage, so there's only one balance to attach.
-    #[allow(unused_variables)]
+    /// Deposits a storage balance for `account_id` (defaults to the caller), which pays for the
+    /// storage of the account's tasks. With `registration_only`, registration keeps the minimum balance
+    /// & anything above is refunded, otherwise the whole deposit is added.
+    /// NOTE: Without `registration_only`, the caller is registered as an agent paid out to `account_id`,
+    /// as in earlier versions. This is deprecated, agents register with `register_agent` instead.
+    ///
+    /// ```bash
+    /// near call cron.testnet storage_deposit '{"account_id": "YOU.testnet", "registration_only": false}' --accountId YOU.testnet --amount 1
+    /// ```
+    #[payable]
     fn storage_deposit(
         &mut self,
         account_id: Option<ValidAccountId>,
         registration_only: Option<bool>,
     ) -> StorageBalance {
-        self.register_agent(account_id.clone());
-        let account_id = account_id
+        if registration_only.is_none() {
+            log!("Registering agents with storage_deposit is deprecated, use register_agent instead");
+            self.register_agent(account_id);
+            return self
+                .internal_storage_balance_of(&env::predecessor_account_id())
+                .unwrap();
+        }
+
+        let amount = env::attached_deposit();
+        let account_id: AccountId = account_id
             .map(|a| a.into())
             .unwrap_or_else(|| env::predecessor_account_id());
+        let min_balance = self.storage_balance_bounds().min.0;
+
+        let (mut account, amount) = match self.storage_accounts.get(&account_id) {
+            Some(account) => (account, amount),
+            None => {
+                assert!(
+                    amount >= min_balance,
+                    "The attached deposit is less than the minimum storage balance of {}",
+                    min_balance
+                );
+                let account = StorageAccount {
+                    total: min_balance,
+                    used: min_balance,
+                };
+                (account, amount - min_balance)
+            }
+        };
+
+        if registration_only == Some(true) {
+            if amount > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(amount);
+            }
+        } else {
+            account.total += amount;
+        }
+        self.storage_accounts.insert(&account_id, &account);
         self.internal_storage_balance_of(&account_id).unwrap()
     }
 
-    /// While storage_withdraw normally allows the caller to retrieve `available` balance, this
-    /// contract sets storage_balance_bounds.min = storage_balance_bounds.max,
-    /// which means available balance will always be 0. So this implementation:
-    /// * panics if `amount > 0`
-    /// * never transfers Ⓝ to caller
-    /// * returns a `storage_balance` struct if `amount` is 0
+    /// Withdraws `amount` (defaults to all) of the available task owner storage balance.
+    /// The agent storage balance is fixed, so agents can only withdraw an `amount` of 0.
+    ///
+    /// ```bash
+    /// near call cron.testnet storage_withdraw '{"amount": "1000000000000000000000000"}' --accountId YOU.testnet --amount 0.000000000000000000000001
+    /// ```
+    #[payable]
     fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
         assert_one_yocto();
         let predecessor = env::predecessor_account_id();
-        if let Some(storage_balance) = self.internal_storage_balance_of(&predecessor) {
+        if let Some(mut account) = self.storage_accounts.get(&predecessor) {
+            let available = account.total - account.used;
+            let amount = amount.map(|a| a.0).unwrap_or(available);
+            assert!(
+                amount <= available,
+                "The amount is greater than the available storage balance of {}",
+                available
+            );
+            if amount > 0 {
+                account.total -= amount;
+                self.storage_accounts.insert(&predecessor, &account);
+                Promise::new(predecessor.clone()).transfer(amount);
+            }
+            self.internal_storage_balance_of(&predecessor).unwrap()
+        } else if let Some(storage_balance) = self.internal_storage_balance_of(&predecessor) {
             match amount {
                 Some(amount) if amount.0 > 0 => {
                     let panic_msg = format!("The amount is greater than the available storage balance. Remember there's a minimum balance needed for an agent's storage. That minimum is {}. To unregister an agent, use the 'unregister_agent' or 'storage_unregister' with the 'force' option.", self.agent_storage_usage);
@@ -58,36 +199,28 @@ impl StorageManagement for Contract {
         }
     }
 
+    /// Closes the task owner storage account & removes the agent, refunding their balances
+    ///
+    /// ```bash
+    /// near call cron.testnet storage_unregister '{}' --accountId YOU.testnet --amount 0.000000000000000000000001
+    /// ```
+    #[payable]
     fn storage_unregister(&mut self, force: Option<bool>) -> bool {
         assert_one_yocto();
         let account_id = env::predecessor_account_id();
         let force = force.unwrap_or(false);
-        if let Some(agent) = self.agents.get(&account_id) {
-            let balance = agent.balance.0;
-            if balance == 0 || force {
-                self.agents.remove(&account_id);
-                // We add 1 to reimburse for the 1 yoctoⓃ used to call this method
-                Promise::new(account_id.clone()).transfer(balance + 1);
-                log!(
-                    "Agent has been removed and refunded the storage cost of {}",
-                    balance + 1
-                );
-                true
-            } else {
-                env::panic(b"Can't unregister the agent with the positive balance. Must use the 'force' parameter if desired.")
-            }
-        } else {
-            log!("The agent {} is not registered", &account_id);
-            false
-        }
+        let agent_removed = self.internal_unregister_agent(&account_id, force);
+        let owner_removed = self.internal_unregister_task_owner(&account_id, !agent_removed);
+        owner_removed || agent_removed
     }
 
+    /// The minimum covers an agent or task owner registration, task owners can deposit more for task storage
     fn storage_balance_bounds(&self) -> StorageBalanceBounds {
         let required_storage_balance =
             Balance::from(self.agent_storage_usage) * env::storage_byte_cost();
         StorageBalanceBounds {
             min: required_storage_balance.into(),
-            max: Some(required_storage_balance.into()),
+            max: None,
         }
     }
 
@@ -95,3 +228,193 @@ impl StorageManagement for Contract {
         self.internal_storage_balance_of(account_id.as_ref())
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::{TaskHash, ONE_NEAR};
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    const BLOCK_START_BLOCK: u64 = 52_201_040;
+    const BLOCK_START_TS: u64 = 1_624_151_503_447_000_000;
+
+    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .signer_account_pk(b"ed25519:4ZhGmuKTfQn9ZpHCQVRwEr4JnutL8Uu3kArfxEqksfVM".to_vec())
+            .predecessor_account_id(predecessor_account_id)
+            .block_index(BLOCK_START_BLOCK)
+            .block_timestamp(BLOCK_START_TS);
+        builder
+    }
+
+    fn create_sample_task(contract: &mut Contract) -> TaskHash {
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 0 */1 * * *".to_string(),
+            Some(false),
+            Some(U128::from(100)),
+            Some(200),
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_storage_deposit_for_account() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let min = contract.storage_balance_bounds().min.0;
+
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        let balance = contract.storage_deposit(Some(accounts(2)), Some(false));
+        assert_eq!(balance.total.0, ONE_NEAR);
+        assert_eq!(balance.available.0, ONE_NEAR - min);
+        assert!(contract.storage_balance_of(accounts(1)).is_none());
+
+        // Deposits add up
+        contract.storage_deposit(Some(accounts(2)), Some(false));
+        let balance = contract.storage_balance_of(accounts(2)).unwrap();
+        assert_eq!(balance.total.0, 2 * ONE_NEAR);
+        assert_eq!(balance.available.0, 2 * ONE_NEAR - min);
+    }
+
+    #[test]
+    fn test_storage_deposit_registration_only() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let min = contract.storage_balance_bounds().min.0;
+
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, min);
+        assert_eq!(balance.available.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit is less than the minimum storage balance")]
+    fn test_storage_deposit_below_min() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(1).build());
+        contract.storage_deposit(None, Some(false));
+    }
+
+    #[test]
+    fn test_storage_prepaid_task() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, Some(false));
+        let available = contract.storage_balance_of(accounts(1)).unwrap().available.0;
+
+        // The attached deposit only needs to cover executions
+        testing_env!(context.attached_deposit(1000000000020000000100).build());
+        let task_hash = create_sample_task(&mut contract);
+        let task = contract.get_task(task_hash.clone());
+        assert!(task.storage_prepaid);
+        assert_eq!(task.total_deposit.0, 1000000000020000000100);
+        let balance = contract.storage_balance_of(accounts(1)).unwrap();
+        assert_eq!(balance.available.0, available - task.storage_deposit.0);
+
+        // Removing the task makes the storage available again
+        testing_env!(context.attached_deposit(0).build());
+        contract.remove_task(task_hash);
+        let balance = contract.storage_balance_of(accounts(1)).unwrap();
+        assert_eq!(balance.available.0, available);
+    }
+
+    #[test]
+    fn test_storage_withdraw() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let min = contract.storage_balance_bounds().min.0;
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, Some(false));
+
+        testing_env!(context.attached_deposit(1).build());
+        let balance = contract.storage_withdraw(Some(U128::from(ONE_NEAR / 2)));
+        assert_eq!(balance.total.0, ONE_NEAR / 2);
+
+        // Withdraws all available by default
+        let balance = contract.storage_withdraw(None);
+        assert_eq!(balance.total.0, min);
+        assert_eq!(balance.available.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount is greater than the available storage balance")]
+    fn test_storage_withdraw_reserved() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, Some(false));
+        testing_env!(context.attached_deposit(1000000000020000000100).build());
+        create_sample_task(&mut contract);
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.storage_withdraw(Some(U128::from(ONE_NEAR)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister while tasks reserve storage, remove the tasks first")]
+    fn test_storage_unregister_with_tasks() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, Some(false));
+        testing_env!(context.attached_deposit(1000000000020000000100).build());
+        create_sample_task(&mut contract);
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn test_storage_unregister() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, Some(false));
+
+        // The 1 yoctoⓃ attached is refunded along with the storage balance
+        testing_env!(context.attached_deposit(1).build());
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(accounts(1)).is_none());
+        let refund = format!("\"amount\":\"{}\"", ONE_NEAR + 1);
+        assert!(get_logs().iter().any(|log| log.contains(&refund)));
+    }
+
+    #[test]
+    fn test_storage_deposit_registers_agent() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let min = contract.storage_balance_bounds().min.0;
+
+        // Deprecated, kept for callers registering agents the earlier way
+        testing_env!(context.attached_deposit(min).build());
+        let balance = contract.storage_deposit(Some(accounts(2)), None);
+        assert_eq!(
+            get_logs()[0],
+            "Registering agents with storage_deposit is deprecated, use register_agent instead"
+        );
+        assert_eq!(balance.total.0, min);
+        assert_eq!(balance.available.0, 0);
+        let agent = contract.get_agent(accounts(1).to_string()).unwrap();
+        assert_eq!(agent.payable_account_id, accounts(2).to_string());
+        assert!(contract.storage_accounts.get(&accounts(1).to_string()).is_none());
+    }
+}
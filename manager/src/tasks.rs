@@ -1,4 +1,116 @@
 use crate::*;
+use near_sdk::serde::{de, Deserializer, Serializer};
+
+/// Fixed size sha256 hash identifying a task, base64 encoded in JSON
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct TaskHash(pub [u8; 32]);
+
+impl Serialize for TaskHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Base64VecU8::from(self.0.to_vec()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TaskHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = Base64VecU8::deserialize(deserializer)?.0;
+        if bytes.len() != 32 {
+            return Err(de::Error::custom("Task hash must be 32 bytes"));
+        }
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&bytes);
+        Ok(TaskHash(hash))
+    }
+}
+
+/// Defines which task runs next within a slot
+/// Ties are always broken by the order tasks were added to the slot
+#[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SlotOrdering {
+    /// First in, first out, by insertion into the slot
+    Fifo,
+    /// Highest tip first
+    Priority,
+    /// Oldest task first, by creation time
+    CreatedAt,
+}
+
+/// Defines how a recurring task handles runs missed while its slot was overdue
+#[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum CatchUpPolicy {
+    /// Drop the overdue run and every missed run, continue at the next future slot
+    Skip,
+    /// Run once for all the missed runs
+    Once,
+    /// Run every missed run in the following slots, up to the given max
+    All(u64),
+}
+
+/// Defines what slot ids are keyed by
+#[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SlotMode {
+    /// Block height, estimated from the cadence using the blocks per second average
+    Block,
+    /// Block timestamp in nanoseconds, unaffected by changes in block time
+    Timestamp,
+}
+
+/// Contract method called once a task runs low on balance, see `set_balance_notify`
+#[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BalanceNotify {
+    /// Account & method called with the task hash & remaining balance
+    pub contract_id: AccountId,
+    pub function_id: String,
+
+    /// Executions left below which the method is called
+    pub threshold: u64,
+
+    /// Whether the method was called since the balance was last above the threshold
+    pub notified: bool,
+}
+
+/// Defines what a task, or task action, executes
+#[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ActionKind {
+    /// Calls `function_id` on `contract_id`, attaching the deposit & gas
+    FunctionCall,
+    /// Sends the deposit to `contract_id`, without calling any function
+    /// Only the gas of the transfer itself is charged, see `GAS_FOR_TRANSFER`
+    Transfer,
+}
+
+impl Default for ActionKind {
+    fn default() -> Self {
+        ActionKind::FunctionCall
+    }
+}
+
+/// Call executed after the task's own call, see `Task::actions`
+#[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TaskAction {
+    #[serde(default)]
+    pub kind: ActionKind,
+    pub contract_id: AccountId,
+    #[serde(default)]
+    pub function_id: String,
+    pub arguments: Base64VecU8,
+    pub deposit: U128,
+    pub gas: Gas,
+}
+
+/// Contract method called with the outcome of each execution, see `set_on_result`
+#[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ResultCallback {
+    pub contract_id: AccountId,
+    pub function_id: String,
+}
 
 #[derive(BorshDeserialize, BorshSerialize, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(crate = "near_sdk::serde")]
@@ -12,6 +124,9 @@ pub struct Task {
     /// Contract method this task will be executing
     pub function_id: String,
 
+    /// Whether the task calls `function_id` or only transfers its deposit to `contract_id`
+    pub kind: ActionKind,
+
     /// Crontab Spec String
     /// Defines the interval spacing of execution
     pub cadence: String,
@@ -22,6 +137,38 @@ pub struct Task {
     /// Total balance of NEAR available for current and future executions
     pub total_deposit: U128,
 
+    /// Balance reserved for the storage this task uses, returned along with the remaining balance on exit
+    pub storage_deposit: U128,
+
+    /// Whether `storage_deposit` was reserved from the owner's storage balance (NEP-145) instead of
+    /// the attached deposit, it is released back to the storage balance on exit
+    pub storage_prepaid: bool,
+
+    /// Max total the task can draw from the payer's credit balance once `total_deposit` runs out,
+    /// unlimited if not set. See `deposit_credit`.
+    pub credit_cap: Option<U128>,
+
+    /// Total drawn from the payer's credit balance so far, reset when the sponsorship changes
+    pub credit_used: U128,
+
+    /// Account paying for executions instead of the owner, see `sponsor_task`.
+    /// The remaining balance goes back to the sponsor on exit, the owner keeps control of the task.
+    pub sponsor_id: Option<AccountId>,
+
+    /// Account the owner allows to sponsor the task, see `approve_sponsor`
+    pub approved_sponsor_id: Option<AccountId>,
+
+    /// Owner balance set aside while a sponsor pays for executions,
+    /// restored once the sponsorship is revoked & refunded to the owner on exit
+    pub owner_deposit: U128,
+
+    /// Contract method called once the task runs low on balance
+    pub balance_notify: Option<BalanceNotify>,
+
+    /// Contract method called with the task hash, execution index, success & returned value
+    /// after each execution
+    pub on_result: Option<ResultCallback>,
+
     /// Configuration of NEAR balance to send to each function call. This is the "amount" for a function call.
     pub deposit: U128,
 
@@ -30,6 +177,94 @@ pub struct Task {
 
     // NOTE: Only allow static pre-defined bytes
     pub arguments: Base64VecU8,
+
+    /// Following calls, executed in order once the previous call succeeded, see `callback_for_task_action`
+    /// A failed call skips the remaining ones. Each execution pays for all of them.
+    pub actions: Vec<TaskAction>,
+
+    /// Extra NEAR paid to the executing agent on each execution.
+    /// Tasks with higher tips are executed first within a slot, see `SlotOrdering::Priority`.
+    pub tip: U128,
+
+    /// Block timestamp the task was created at
+    pub created_at: U64,
+
+    /// Window in blocks (seconds with `SlotMode::Timestamp`) the task is spread over within its cadence,
+    /// defaults to `slot_jitter`
+    pub jitter: Option<u64>,
+
+    /// Policy for runs missed while the task was overdue, see `CatchUpPolicy`
+    pub catch_up: CatchUpPolicy,
+
+    /// Missed runs still to be caught up, and the total of runs that were dropped
+    pub catch_up_remaining: u64,
+    pub skipped_runs: u64,
+
+    /// Total of executions so far, the index of the next execution
+    pub executions: u64,
+
+    /// Intended fire timestamp of the scheduled run, the following run is computed from it
+    /// NOTE: In the past while missed runs are being caught up
+    pub next_ts: U64,
+
+    /// Slot the task is currently scheduled in
+    /// NOTE: Can be later than the cadence implies, when slots are at capacity
+    pub next_slot: U128,
+
+    /// Staking yield accounting, see `YieldDistribution::Tasks`
+    /// Yield is refunded to the task owner along with any remaining balance
+    pub yield_checkpoint: U128,
+    pub accrued_yield: U128,
+}
+
+/// Optional settings of `create_task`, see its docs
+#[derive(Default, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TaskOptions {
+    pub tip: Option<U128>,
+    pub jitter: Option<u64>,
+    pub catch_up: Option<CatchUpPolicy>,
+    pub credit_cap: Option<U128>,
+    pub actions: Option<Vec<TaskAction>>,
+    pub kind: Option<ActionKind>,
+}
+
+impl Task {
+    /// Account funding the task balance & executions, the sponsor if set, otherwise the owner
+    pub fn payer_id(&self) -> &AccountId {
+        self.sponsor_id.as_ref().unwrap_or(&self.owner_id)
+    }
+
+    /// Gas of the task's own call & all following actions
+    pub fn total_gas(&self) -> Gas {
+        self.gas + self.actions.iter().map(|a| a.gas).sum::<Gas>()
+    }
+
+    /// Gas charged to the task each execution, including the balance notification if set,
+    /// and the callback reporting the result along with the `on_result` call if set
+    pub fn charged_gas(&self) -> Gas {
+        self.total_gas() + Self::extra_gas(self.on_result.is_some(), self.balance_notify.is_some())
+    }
+
+    /// Gas charged on top of the task's calls for reporting results & the balance notification
+    pub fn extra_gas(on_result: bool, balance_notify: bool) -> Gas {
+        let notify_gas = if balance_notify {
+            GAS_FOR_BALANCE_NOTIFY
+        } else {
+            0
+        };
+        let result_gas = if on_result {
+            GAS_FOR_CALLBACK + GAS_FOR_ON_RESULT
+        } else {
+            0
+        };
+        notify_gas + result_gas
+    }
+
+    /// Deposit attached to the task's own call & all following actions
+    pub fn total_call_deposit(&self) -> Balance {
+        self.deposit.0 + self.actions.iter().map(|a| a.deposit.0).sum::<Balance>()
+    }
 }
 
 #[near_bindgen]
@@ -38,20 +273,36 @@ impl Contract {
     /// contract, function id & other settings. When the task runs out of balance
     /// the task is no longer executed, any additional funds will be returned to task owner.
     ///
+    /// Optional Parameters, passed in "options":
+    /// "tip" - Amount paid to the agent on top of the agent fee each execution, prioritizing this task within its slot.
+    /// "jitter" - Window in blocks the task can be delayed by, spreading load for popular cadences. 0 disables it.
+    /// "catch_up" - How missed runs are handled after the task fell behind: "Skip", "Once" (default) or {"All": max}.
+    /// "credit_cap" - Max total drawn from the owner's credit balance when the task balance runs out. 0 disables it.
+    /// "actions" - Following calls, each executed once the previous one succeeded. Up to `MAX_TASK_ACTIONS`.
+    /// "kind" - "FunctionCall" (default) or "Transfer", sending "deposit" to "contract_id" without a function call.
+    ///          Transfers don't need a "function_id", "arguments" & "gas" are ignored, only the gas of the transfer is charged.
+    ///
+    /// NOTE: The storage the task uses is reserved from the owner's storage balance, see `storage_deposit`,
+    /// or from the attached deposit when the storage balance doesn't cover it.
+    /// Arguments of the task & of each action are limited to `max_arguments_size` bytes.
+    ///
     /// ```bash
     /// near call cron.testnet create_task '{"contract_id": "counter.in.testnet","function_id": "increment","cadence": "0 0 */1 * * *","recurring": true,"deposit": 0,"gas": 2400000000000}' --accountId YOU.testnet
+    /// near call cron.testnet create_task '{"contract_id": "friend.testnet","cadence": "0 0 */1 * * *","recurring": true,"deposit": "1000000000000000000000000","options": {"kind": "Transfer"}}' --accountId YOU.testnet --amount 25
     /// ```
     #[payable]
+    #[allow(clippy::too_many_arguments)]
     pub fn create_task(
         &mut self,
         contract_id: ValidAccountId,
-        function_id: String,
+        function_id: Option<String>,
         cadence: String,
         recurring: Option<bool>,
         deposit: Option<U128>,
         gas: Option<Gas>,
         arguments: Option<Base64VecU8>,
-    ) -> Base64VecU8 {
+        options: Option<TaskOptions>,
+    ) -> TaskHash {
         // No adding tasks while contract is paused
         assert_eq!(self.paused, false, "Create task paused");
         // check cadence can be parsed
@@ -60,16 +311,93 @@ impl Contract {
             "Cadence string invalid"
         );
 
+        let TaskOptions {
+            tip,
+            jitter,
+            catch_up,
+            credit_cap,
+            actions,
+            kind,
+        } = options.unwrap_or_default();
+        let arguments = arguments.unwrap_or_else(|| Base64VecU8::from(vec![]));
+        let deposit = U128::from(deposit.map(|v| v.0).unwrap_or(0u128));
+        let kind = kind.unwrap_or_default();
+        let mut actions = actions.unwrap_or_default();
+        assert!(
+            actions.len() as u64 <= MAX_TASK_ACTIONS,
+            "Too many actions, the max is {}",
+            MAX_TASK_ACTIONS
+        );
+        for args in std::iter::once(&arguments).chain(actions.iter().map(|a| &a.arguments)) {
+            assert!(
+                args.0.len() as u64 <= self.max_arguments_size,
+                "Arguments exceed the max size of {} bytes",
+                self.max_arguments_size
+            );
+        }
+
+        // Transfers only pay for the gas of the transfer, and need something to send
+        let (function_id, gas) = match kind {
+            ActionKind::FunctionCall => (
+                function_id.expect("Function calls need a function_id"),
+                gas.unwrap_or(GAS_BASE_FEE),
+            ),
+            ActionKind::Transfer => (String::new(), GAS_FOR_TRANSFER),
+        };
+        for action in actions.iter_mut() {
+            if action.kind == ActionKind::Transfer {
+                action.function_id = String::new();
+                action.gas = GAS_FOR_TRANSFER;
+            }
+        }
+        // Every call & callback of an execution is prepaid by the agent's `proxy_call`,
+        // including the notifications the owner can add later
+        let gas_budget = GAS_FOR_PROXY_CALL
+            + gas
+            + Self::task_action_callback_gas(&actions, true)
+            + GAS_FOR_BALANCE_NOTIFY;
+        assert!(
+            gas_budget <= MAX_GAS,
+            "Gas of the task, its actions & callbacks exceeds the max of {}",
+            MAX_GAS
+        );
+        let transfers_funded = std::iter::once((kind, deposit.0))
+            .chain(actions.iter().map(|a| (a.kind, a.deposit.0)))
+            .all(|(kind, deposit)| kind != ActionKind::Transfer || deposit > 0);
+        assert!(transfers_funded, "Transfers need a deposit to send");
+
         let item = Task {
             owner_id: env::predecessor_account_id(),
             contract_id: contract_id.into(),
             function_id,
+            kind,
             cadence,
             recurring: recurring.unwrap_or(false),
             total_deposit: U128::from(env::attached_deposit()),
-            deposit: U128::from(deposit.map(|v| v.0).unwrap_or(0u128)),
-            gas: gas.unwrap_or(GAS_BASE_FEE),
-            arguments: arguments.unwrap_or_else(|| Base64VecU8::from(vec![])),
+            storage_deposit: U128::from(0),
+            storage_prepaid: false,
+            credit_cap,
+            credit_used: U128::from(0),
+            sponsor_id: None,
+            approved_sponsor_id: None,
+            owner_deposit: U128::from(0),
+            balance_notify: None,
+            on_result: None,
+            deposit,
+            gas,
+            arguments,
+            actions,
+            tip: tip.unwrap_or(U128::from(0)),
+            created_at: U64::from(env::block_timestamp()),
+            jitter,
+            catch_up: catch_up.unwrap_or(CatchUpPolicy::Once),
+            catch_up_remaining: 0,
+            skipped_runs: 0,
+            executions: 0,
+            next_ts: U64::from(0),
+            next_slot: U128::from(0),
+            yield_checkpoint: U128::from(self.yield_per_deposit),
+            accrued_yield: U128::from(0),
         };
 
         // Check that balance is sufficient for 1 execution minimum
@@ -85,28 +413,37 @@ impl Contract {
         //     "Gas minimum has not been met, need at least {}",
         //     min_balance_needed
         // );
+        // Executions can also be paid from the owner's credit balance, see `deposit_credit`
+        let credit_available = self.task_credit_available(&item);
         assert!(
-            min_balance_needed <= item.total_deposit.0,
+            min_balance_needed <= item.total_deposit.0 + credit_available,
             "Not enough task balance to execute job, need at least {}",
             min_balance_needed
         );
 
-        let hash = self.hash(&item);
-        // log!("Task Hash (as bytes) {:?}", &hash);
-
-        // Parse cadence into a future timestamp, then convert to a slot
-        let next_slot = self.get_slot_from_cadence(item.cadence.clone());
-
-        // Add task to catalog
-        assert!(self.tasks.insert(&hash, &item).is_none(), "Task already exists");
+        // Reserve the storage used by the task, its slot entry & arguments,
+        // from the owner's storage balance if it covers it, otherwise from the deposit
+        let initial_storage_usage = env::storage_usage();
+        let hash = self.insert_task(item);
+        let storage_cost = Balance::from(env::storage_usage() - initial_storage_usage)
+            * env::storage_byte_cost();
 
-        // Get previous task hashes in slot, add as needed
-        let mut slot_slots = self.slots.get(&next_slot).unwrap_or(Vec::new());
-        slot_slots.push(hash.clone());
-        log!("Task next slot: {}", next_slot);
-        self.slots.insert(&next_slot, &slot_slots);
+        let mut task = self.tasks.get(&hash).expect("No task found by hash");
+        task.storage_prepaid = self.reserve_task_storage(&task.owner_id, storage_cost);
+        if !task.storage_prepaid {
+            let deposit_needed = min_balance_needed.saturating_sub(credit_available) + storage_cost;
+            assert!(
+                deposit_needed <= env::attached_deposit(),
+                "Not enough task balance to cover storage, need at least {}",
+                deposit_needed
+            );
+            task.total_deposit = U128::from(task.total_deposit.0 - storage_cost);
+            self.total_task_deposits -= storage_cost;
+        }
+        task.storage_deposit = U128::from(storage_cost);
+        self.tasks.insert(&hash, &task);
 
-        Base64VecU8::from(hash)
+        hash
     }
 
     /// Deletes a task in its entirety, returning any remaining balance to task owner.
@@ -114,9 +451,8 @@ impl Contract {
     /// ```bash
     /// near call cron.testnet remove_task '{"task_hash": ""}' --accountId YOU.testnet
     /// ```
-    pub fn remove_task(&mut self, task_hash: Base64VecU8) {
-        let hash = task_hash.0;
-        let task = self.tasks.get(&hash).expect("No task found by hash");
+    pub fn remove_task(&mut self, task_hash: TaskHash) {
+        let task = self.tasks.get(&task_hash).expect("No task found by hash");
 
         assert_eq!(
             task.owner_id,
@@ -125,28 +461,200 @@ impl Contract {
         );
 
         // If owner, allow to remove task
-        self.exit_task(hash);
+        self.exit_task(task_hash);
     }
 
-    /// Internal management of finishing a task.
-    /// Responsible for cleaning up storage &
-    /// returning any remaining balance to task owner.
-    fn exit_task(&mut self, task_hash: Vec<u8>) {
-        let task = self.tasks.remove(&task_hash).expect("No task found by hash");
+    /// Allows `sponsor_id` to sponsor the task, see `sponsor_task`. Omitting `sponsor_id` removes the approval.
+    /// Only the owner can approve a sponsor, an ongoing sponsorship isn't affected.
+    ///
+    /// ```bash
+    /// near call cron.testnet approve_sponsor '{"task_hash": "", "sponsor_id": "SPONSOR.testnet"}' --accountId YOU.testnet
+    /// ```
+    pub fn approve_sponsor(&mut self, task_hash: TaskHash, sponsor_id: Option<ValidAccountId>) {
+        let mut task = self.tasks.get(&task_hash).expect("No task found by hash");
+        assert_eq!(
+            task.owner_id,
+            env::predecessor_account_id(),
+            "Only owner can update their task."
+        );
+
+        task.approved_sponsor_id = sponsor_id.map(|a| a.into());
+        self.tasks.insert(&task_hash, &task);
+    }
+
+    /// Sponsors a task, paying its executions from the attached deposit & the sponsor's credit balance.
+    /// The sponsor must be approved by the owner first, see `approve_sponsor`.
+    /// The task balance so far is set aside for the owner, who keeps update & remove rights.
+    /// The remaining balance goes back to the sponsor when the task exits.
+    ///
+    /// ```bash
+    /// near call cron.testnet sponsor_task '{"task_hash": ""}' --accountId SPONSOR.testnet --amount 10
+    /// ```
+    #[payable]
+    pub fn sponsor_task(&mut self, task_hash: TaskHash) {
+        let mut task = self.tasks.get(&task_hash).expect("No task found by hash");
+        assert!(task.sponsor_id.is_none(), "Task already sponsored");
+        assert!(task_hash != self.tick_task_hash, "Tick task is paid by the treasury");
+        assert_eq!(
+            task.approved_sponsor_id,
+            Some(env::predecessor_account_id()),
+            "Sponsor not approved by the task owner"
+        );
+
+        let owner_balance = self.take_task_balance(&mut task);
+        task.owner_deposit = U128::from(owner_balance);
+
+        task.sponsor_id = Some(env::predecessor_account_id());
+        task.credit_used = U128::from(0);
+        task.total_deposit = U128::from(env::attached_deposit());
+        self.total_task_deposits += env::attached_deposit();
+        let balance_needed = self.task_balance_uses(&task);
+        assert!(
+            balance_needed <= task.total_deposit.0 + self.task_credit_available(&task),
+            "Not enough sponsor balance to execute task, need at least {}",
+            balance_needed
+        );
+        self.tasks.insert(&task_hash, &task);
+    }
+
+    /// Ends the sponsorship of a task, refunding the remaining task balance to the sponsor.
+    /// The owner balance set aside by `sponsor_task` pays for following executions again,
+    /// the task exits if the owner can't pay.
+    ///
+    /// ```bash
+    /// near call cron.testnet revoke_sponsorship '{"task_hash": ""}' --accountId SPONSOR.testnet
+    /// ```
+    pub fn revoke_sponsorship(&mut self, task_hash: TaskHash) {
+        let mut task = self.tasks.get(&task_hash).expect("No task found by hash");
+        assert_eq!(
+            task.sponsor_id,
+            Some(env::predecessor_account_id()),
+            "Only the sponsor can revoke sponsorship"
+        );
 
-        // return any balance
-        if task.total_deposit.0 > 0 {
-            Promise::new(task.owner_id.to_string()).transfer(task.total_deposit.0);
+        let refund = self.take_task_balance(&mut task);
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
         }
+        task.sponsor_id = None;
+        task.credit_used = U128::from(0);
+        task.total_deposit = task.owner_deposit;
+        task.owner_deposit = U128::from(0);
+        self.total_task_deposits += task.total_deposit.0;
+        self.tasks.insert(&task_hash, &task);
+    }
+
+    /// Adds the attached deposit to the task balance, funding future executions.
+    /// Only the payer of the task, its sponsor if set, otherwise its owner, can refill it.
+    ///
+    /// ```bash
+    /// near call cron.testnet refill_task '{"task_hash": ""}' --accountId YOU.testnet --amount 10
+    /// ```
+    #[payable]
+    pub fn refill_task(&mut self, task_hash: TaskHash) {
+        let mut task = self.tasks.get(&task_hash).expect("No task found by hash");
+        assert_eq!(
+            task.payer_id(),
+            &env::predecessor_account_id(),
+            "Only the task payer can refill the task"
+        );
+
+        self.settle_task_yield(&mut task);
+        task.total_deposit = U128::from(task.total_deposit.0 + env::attached_deposit());
+        self.total_task_deposits += env::attached_deposit();
+        self.tasks.insert(&task_hash, &task);
+    }
+
+    /// Registers a contract method the manager calls with the task hash & remaining balance,
+    /// once the executions the task can still pay for fall below `threshold` (defaults to 3).
+    /// The method is called again only after the balance went back above the threshold.
+    /// Omitting `contract_id` removes the notification.
+    ///
+    /// ```bash
+    /// near call cron.testnet set_balance_notify '{"task_hash": "", "contract_id": "YOU.testnet", "function_id": "refill", "threshold": 3}' --accountId YOU.testnet
+    /// ```
+    pub fn set_balance_notify(
+        &mut self,
+        task_hash: TaskHash,
+        contract_id: Option<ValidAccountId>,
+        function_id: Option<String>,
+        threshold: Option<u64>,
+    ) {
+        let mut task = self.tasks.get(&task_hash).expect("No task found by hash");
+        assert_eq!(
+            task.owner_id,
+            env::predecessor_account_id(),
+            "Only owner can update their task."
+        );
+
+        task.balance_notify = contract_id.map(|contract_id| BalanceNotify {
+            contract_id: contract_id.into(),
+            function_id: function_id.expect("Function id is required"),
+            threshold: threshold.unwrap_or(BALANCE_NOTIFY_THRESHOLD),
+            notified: false,
+        });
+        self.tasks.insert(&task_hash, &task);
+    }
+
+    /// Registers a contract method the manager calls after each execution of the task with
+    /// the task hash, the execution index, whether the call succeeded & the value it returned.
+    /// Omitting `contract_id` removes the callback.
+    ///
+    /// ```bash
+    /// near call cron.testnet set_on_result '{"task_hash": "", "contract_id": "YOU.testnet", "function_id": "on_result"}' --accountId YOU.testnet
+    /// ```
+    pub fn set_on_result(
+        &mut self,
+        task_hash: TaskHash,
+        contract_id: Option<ValidAccountId>,
+        function_id: Option<String>,
+    ) {
+        let mut task = self.tasks.get(&task_hash).expect("No task found by hash");
+        assert_eq!(
+            task.owner_id,
+            env::predecessor_account_id(),
+            "Only owner can update their task."
+        );
+
+        task.on_result = contract_id.map(|contract_id| ResultCallback {
+            contract_id: contract_id.into(),
+            function_id: function_id.expect("Function id is required"),
+        });
+        self.tasks.insert(&task_hash, &task);
+    }
 
-        // Remove task from schedule
-        // Get previous task hashes in slot, find index of task hash, remove
-        let next_slot = self.get_slot_from_cadence(task.cadence.clone());
-        let mut slot_tasks = self.slots.get(&next_slot).unwrap_or(Vec::new());
-        if slot_tasks.len() != 0 {
-            slot_tasks.retain(|h| h != &task_hash);
-            self.slots.insert(&next_slot, &slot_tasks);
+    /// Internal management of finishing a task.
+    /// Responsible for cleaning up storage &
+    /// returning any remaining balance to task owner, or sponsor.
+    pub(crate) fn exit_task(&mut self, task_hash: TaskHash) {
+        // Removing swaps the last task into the freed index, `reslot_tasks` would skip it
+        assert!(self.reslot_cursor.is_none(), "Re-slotting tasks, catalog changes paused");
+        let mut task = self.tasks.remove(&task_hash).expect("No task found by hash");
+
+        // return any balance, including accrued staking yield, to the payer
+        // the storage deposit always goes back to the owner
+        let balance_refund = self.take_task_balance(&mut task);
+        let mut owner_refund = 0;
+        if task.storage_prepaid {
+            self.release_task_storage(&task.owner_id, task.storage_deposit.0);
+        } else {
+            owner_refund += task.storage_deposit.0;
+        }
+        // the owner balance set aside during a sponsorship goes back to the owner
+        owner_refund += task.owner_deposit.0;
+        match &task.sponsor_id {
+            Some(sponsor_id) if balance_refund > 0 => {
+                Promise::new(sponsor_id.clone()).transfer(balance_refund);
+            }
+            Some(_) => {}
+            None => owner_refund += balance_refund,
         }
+        if owner_refund > 0 {
+            Promise::new(task.owner_id.to_string()).transfer(owner_refund);
+        }
+
+        // Remove task from schedule, if it is still slotted
+        self.slot_remove(task.next_slot.0, &task_hash);
     }
 
     /// Executes a task based on the current task slot
@@ -162,6 +670,7 @@ impl Contract {
     pub fn proxy_call(&mut self) {
         // No adding tasks while contract is paused
         assert_eq!(self.paused, false, "Task execution paused");
+        assert!(self.reslot_cursor.is_none(), "Re-slotting tasks, execution paused");
 
         // only registered agent signed, because micropayments will benefit long term
         let agent_opt = self.agents.get(&env::predecessor_account_id());
@@ -169,51 +678,100 @@ impl Contract {
             env::panic(b"Agent not registered");
         }
         let mut agent = agent_opt.unwrap();
+        self.assert_agent_whitelisted(&env::predecessor_account_id());
+
+        // Keep the blocks per second estimate fresh between ticks
+        self.record_bps_sample(BPS_SAMPLE_INTERVAL);
 
         // Get current slot based on block or timestamp
         let current_slot = self.get_slot_id(None);
 
-        // get task based on current slot
-        // priority goes to tasks that have fallen behind (using floor key)
-        let (slot_opt, slot_ballpark) = if let Some(k) = self.slots.floor_key(&current_slot) {
-            (self.slots.get(&k), k)
-        } else {
-            env::log(b"aloha ow my brain");
-            (self.slots.get(&current_slot), current_slot)
+        // Pop the next due task, an unfunded tick is skipped over without holding up the agent
+        let (hash, slot_ballpark, agent_fee) = match self.pop_due_task(current_slot) {
+            Some(due) => due,
+            None => return,
         };
 
-        let mut slot_data = slot_opt.expect("No tasks found in slot");
-
-        // Get a single task hash, then retrieve task details
-        let hash = slot_data.pop().expect("No tasks available");
+        let mut task = self.tasks.get(&hash).expect("No task found by hash");
 
-        // After popping, ensure state is rewritten back
-        if slot_data.is_empty() {
-            // Clean up slot if no more data
-            self.slots.remove(&slot_ballpark);
-            log!("Slot {} cleaned");
-        } else {
-            self.slots.insert(&slot_ballpark, &slot_data);
+        // Apply the catch-up policy when following runs came due while the slot was overdue
+        // Dropped runs are skipped over by moving the intended fire timestamp forward
+        if task.recurring && task.catch_up_remaining == 0 {
+            let slot_end_ts = self.get_slot_timestamp(slot_ballpark + self.slot_width());
+            let missed =
+                self.get_missed_runs(&task.cadence, core::cmp::max(task.next_ts.0, slot_end_ts));
+            if let Some(last_missed) = missed.last() {
+                let missed_runs = missed.len() as u64;
+                let dropped = match task.catch_up {
+                    CatchUpPolicy::Skip => {
+                        // The overdue run is dropped as well, nothing gets executed
+                        task.skipped_runs += missed_runs + 1;
+                        task.next_ts = U64::from(*last_missed);
+                        log_event(
+                            "task_skip",
+                            json!({ "task_hash": hash, "runs": missed_runs + 1 }),
+                        );
+                        // The agent is still paid the agent fee for its call
+                        self.settle_task_yield(&mut task);
+                        if agent_fee > task.total_deposit.0 {
+                            let shortfall = agent_fee - task.total_deposit.0;
+                            self.draw_task_credit(&mut task, shortfall);
+                        }
+                        if agent_fee > task.total_deposit.0 {
+                            log!("Not enough task balance to execute task, exiting");
+                            return self.exit_task(hash);
+                        }
+                        self.total_task_deposits -= agent_fee;
+                        task.total_deposit = U128::from(task.total_deposit.0 - agent_fee);
+                        agent.balance = U128::from(agent.balance.0 + agent_fee);
+                        self.agents.insert(&env::signer_account_id(), &agent);
+                        return self.reschedule_task(&hash, task, current_slot);
+                    }
+                    CatchUpPolicy::Once => missed_runs,
+                    CatchUpPolicy::All(max) => {
+                        task.catch_up_remaining = core::cmp::min(missed_runs, max);
+                        missed_runs - task.catch_up_remaining
+                    }
+                };
+                if dropped > 0 {
+                    task.skipped_runs += dropped;
+                    task.next_ts = U64::from(missed[dropped as usize - 1]);
+                }
+            }
         }
 
-        let mut task = self.tasks.get(&hash).expect("No task found by hash");
-
         // Fee breakdown:
         // - Used Gas: Task Txn Fee Cost
         // - Agent Fee: Incentivize Execution SLA
         //
         // Task Fee Examples:
         // Total Fee = Gas Fee + Agent Fee
-        // Total Balance = Task Deposit + Total Fee 
+        // Total Balance = Task Deposit + Total Fee + Tip
         //
         // NOTE: Gas cost includes the cross-contract call & internal logic of this contract.
         // Direct contract gas fee will be lower than task execution costs, however
         // we require the task owner to appropriately estimate gas for overpayment.
         // The gas overpayment will also accrue to the agent since there is no way to read
         // how much gas was actually used on callback.
-        let call_fee_used = u128::from(task.gas) * self.gas_price;
-        let call_total_fee = call_fee_used + self.agent_fee;
-        let call_total_balance = task.deposit.0 + call_total_fee;
+        // Following actions are paid for along with the task's own call
+        let call_fee_used = u128::from(task.charged_gas()) * self.gas_price;
+        let call_total_fee = call_fee_used + agent_fee;
+        let call_total_balance = task.total_call_deposit() + call_total_fee + task.tip.0;
+
+        // The manager's own tick task was already paid for by the treasury, not a user balance
+        let is_tick = hash == self.tick_task_hash;
+        if is_tick {
+            self.settle_task_yield(&mut task);
+            self.total_task_deposits += call_total_balance;
+            task.total_deposit = U128::from(task.total_deposit.0 + call_total_balance);
+        }
+
+        // Top up from the payer's credit balance once the task balance runs out
+        if !is_tick && call_total_balance > task.total_deposit.0 {
+            self.settle_task_yield(&mut task);
+            let shortfall = call_total_balance - task.total_deposit.0;
+            self.draw_task_credit(&mut task, shortfall);
+        }
 
         // safety check and not burn too much gas.
         if call_total_balance > task.total_deposit.0 {
@@ -222,34 +780,84 @@ impl Contract {
             return self.exit_task(hash);
         }
 
+        // Protocol share of the fee accrues to the treasury, the remainder goes to the agent
+        let treasury_share = call_total_fee * u128::from(self.treasury_fee)
+            / u128::from(TREASURY_FEE_DENOMINATOR);
+        self.available_balance += treasury_share;
+        self.treasury_total_collected += treasury_share;
+
+        // Staking yield assigned to agents is paid out as a bonus, up to one agent fee per execution
+        let agent_bonus = core::cmp::min(self.agent_reward_pool, self.agent_fee);
+        self.agent_reward_pool -= agent_bonus;
+
         // Update agent storage
         // Increment agent reward & task count
         // Reward for agent MUST include the amount of gas used as a reimbursement
-        agent.balance = U128::from(agent.balance.0 + call_total_fee);
+        // Tips go to the agent in full
+        agent.balance = U128::from(
+            agent.balance.0 + call_total_fee - treasury_share + agent_bonus + task.tip.0,
+        );
         agent.total_tasks_executed = U128::from(agent.total_tasks_executed.0 + 1);
         self.agents.insert(&env::signer_account_id(), &agent);
 
         // Decrease task balance, Update task storage
+        self.settle_task_yield(&mut task);
+        self.total_task_deposits = self.total_task_deposits.saturating_sub(call_total_balance);
         task.total_deposit = U128::from(task.total_deposit.0 - call_total_balance);
+        let execution_index = task.executions;
+        task.executions += 1;
+
+        // Let the owner know once the task is running low on balance, including credit
+        let balance_left = task.total_deposit.0 + self.task_credit_available(&task);
+        if task.recurring && !is_tick {
+            self.notify_low_balance(&hash, &mut task, balance_left, call_total_balance);
+        }
         self.tasks.insert(&hash, &task);
 
         // Call external contract with task variables
-        let promise_first = env::promise_create(
+        let promise_first = Self::create_action_promise(
+            task.kind,
             task.contract_id.clone(),
-            &task.function_id.as_bytes(),
-            task.arguments.0.as_slice(),
+            &task.function_id,
+            &task.arguments,
             task.deposit.0,
             task.gas,
         );
 
-        // if out of balance or non-recurring, exit callback
-        if !task.recurring || call_total_balance > task.total_deposit.0 {
+        // if out of balance, including credit, or non-recurring, exit
+        let exiting = !task.recurring || (!is_tick && call_total_balance > balance_left);
+        if exiting {
             // Process task exit, if no future task can execute
-            self.exit_task(hash);
-            env::promise_return(promise_first);
-        } else {
+            self.exit_task(hash.clone());
+        }
+
+        let promise_second = if !task.actions.is_empty() {
+            // Following actions are executed one by one, rescheduling once they are done
+            let current_slot = if exiting { None } else { Some(U128::from(current_slot)) };
+            env::promise_then(
+                promise_first,
+                env::current_account_id(),
+                b"callback_for_task_action",
+                json!({
+                    "task_hash": hash,
+                    "payer_id": task.payer_id(),
+                    "deposit": task.deposit,
+                    "actions": task.actions,
+                    "current_slot": current_slot,
+                    "execution_index": U64::from(execution_index),
+                    "on_result": task.on_result
+                }).to_string().as_bytes(),
+                0,
+                Self::task_action_callback_gas(&task.actions, task.on_result.is_some()),
+            )
+        } else if !exiting {
             // if recurring, callback for scheduling
-            let promise_second = env::promise_then(
+            let callback_gas = if task.on_result.is_some() {
+                GAS_FOR_CALLBACK + GAS_FOR_ON_RESULT
+            } else {
+                GAS_FOR_CALLBACK
+            };
+            env::promise_then(
                 promise_first,
                 env::current_account_id(),
                 b"callback_for_proxy_call",
@@ -258,70 +866,615 @@ impl Contract {
                     "current_slot": U128::from(current_slot)
                 }).to_string().as_bytes(),
                 0,
-                GAS_FOR_CALLBACK,
-            );
-            env::promise_return(promise_second);
-        }   
+                callback_gas,
+            )
+        } else if task.on_result.is_some() || task.deposit.0 > 0 {
+            // The task is gone by then, so the callback gets the result & refund details directly
+            let callback_gas = if task.on_result.is_some() {
+                GAS_FOR_CALLBACK + GAS_FOR_ON_RESULT
+            } else {
+                GAS_FOR_CALLBACK
+            };
+            env::promise_then(
+                promise_first,
+                env::current_account_id(),
+                b"callback_for_task_result",
+                json!({
+                    "task_hash": hash,
+                    "payer_id": task.payer_id(),
+                    "deposit": task.deposit,
+                    "execution_index": U64::from(execution_index),
+                    "on_result": task.on_result
+                }).to_string().as_bytes(),
+                0,
+                callback_gas,
+            )
+        } else {
+            promise_first
+        };
+        env::promise_return(promise_second);
     }
 
     /// Logic executed on the completion of a proxy call
-    /// Reschedule next task
+    /// Reschedule next task, then report the result if the task has an `on_result` callback
+    /// The deposit of a failed call comes back to the manager, it is credited back to the task
     #[private]
-    pub fn callback_for_proxy_call(&mut self, task_hash: Vec<u8>, current_slot: U128) {
-        let task = self
-            .tasks
-            .get(&task_hash.clone())
-            .expect("No task found by hash");
-
-        // TODO: double check this can't get scheduled in current slot again
-        let next_slot = self.get_slot_from_cadence(task.cadence.clone());
-        log!("Scheduling Next Task {:?}", &next_slot);
-        assert!(
-            &current_slot.0 < &next_slot,
-            "Cannot schedule task in the past"
-        );
+    pub fn callback_for_proxy_call(&mut self, task_hash: TaskHash, current_slot: U128) {
+        let task = self.tasks.get(&task_hash).expect("No task found by hash");
+        let on_result = task.on_result.clone();
+        let execution_index = task.executions.saturating_sub(1);
+        let refund = if task.deposit.0 > 0 && Self::call_failed() {
+            task.deposit.0
+        } else {
+            0
+        };
+        let payer_id = task.payer_id().clone();
+        let current_slot = self.in_flight_slot(current_slot.0);
+        self.reschedule_task(&task_hash, task, current_slot);
+        self.refund_task_deposit(&task_hash, &payer_id, refund);
+        if let Some(on_result) = on_result {
+            self.report_task_result(&task_hash, execution_index, &on_result);
+        }
+    }
+
+    /// Logic executed on the completion of each call of a task with following actions
+    /// Executes the next action once the previous call succeeded, a failed call skips the remaining actions.
+    /// The `deposit` of the failed call & of the skipped actions go back to the task,
+    /// or to `payer_id` once the task exited.
+    /// Once done, reschedules the task if `current_slot` is set & reports the result of the last call.
+    #[private]
+    #[allow(clippy::too_many_arguments)]
+    pub fn callback_for_task_action(
+        &mut self,
+        task_hash: TaskHash,
+        payer_id: AccountId,
+        deposit: U128,
+        actions: Vec<TaskAction>,
+        current_slot: Option<U128>,
+        execution_index: U64,
+        on_result: Option<ResultCallback>,
+    ) {
+        let mut actions = actions;
+        let succeeded = !Self::call_failed();
+        if succeeded && !actions.is_empty() {
+            let action = actions.remove(0);
+            let deposit = action.deposit;
+            let promise_action = Self::create_action_promise(
+                action.kind,
+                action.contract_id,
+                &action.function_id,
+                &action.arguments,
+                action.deposit.0,
+                action.gas,
+            );
+            let promise_next = env::promise_then(
+                promise_action,
+                env::current_account_id(),
+                b"callback_for_task_action",
+                json!({
+                    "task_hash": task_hash,
+                    "payer_id": payer_id,
+                    "deposit": deposit,
+                    "actions": actions,
+                    "current_slot": current_slot,
+                    "execution_index": execution_index,
+                    "on_result": on_result
+                }).to_string().as_bytes(),
+                0,
+                Self::task_action_callback_gas(&actions, on_result.is_some()),
+            );
+            return env::promise_return(promise_next);
+        }
+        if !succeeded {
+            if !actions.is_empty() {
+                log!("Task call failed, skipped the {} remaining actions", actions.len());
+            }
+            let refund = deposit.0 + actions.iter().map(|a| a.deposit.0).sum::<Balance>();
+            self.refund_task_deposit(&task_hash, &payer_id, refund);
+        }
+
+        if let Some(current_slot) = current_slot {
+            let task = self.tasks.get(&task_hash).expect("No task found by hash");
+            let current_slot = self.in_flight_slot(current_slot.0);
+            self.reschedule_task(&task_hash, task, current_slot);
+        }
+        if let Some(on_result) = on_result {
+            self.report_task_result(&task_hash, execution_index.0, &on_result);
+        }
+    }
 
-        // Get previous task hashes in slot, add as needed
-        let mut slot_tasks = self.slots.get(&next_slot).unwrap_or_default();
-        slot_tasks.push(task_hash.clone());
-        self.slots.insert(&next_slot, &slot_tasks);
+    /// Logic executed on the completion of the last proxy call of a task with an `on_result` callback or a deposit
+    /// Returns the deposit of a failed call to `payer_id` & reports the result, the task already exited
+    #[private]
+    pub fn callback_for_task_result(
+        &mut self,
+        task_hash: TaskHash,
+        payer_id: AccountId,
+        deposit: U128,
+        execution_index: U64,
+        on_result: Option<ResultCallback>,
+    ) {
+        if deposit.0 > 0 && Self::call_failed() {
+            self.refund_task_deposit(&task_hash, &payer_id, deposit.0);
+        }
+        if let Some(on_result) = on_result {
+            self.report_task_result(&task_hash, execution_index.0, &on_result);
+        }
     }
 }
 
 // Internal methods
 impl Contract {
-    fn hash(&self, item: &Task) -> Vec<u8> {
+    pub(crate) fn hash(&self, item: &Task) -> TaskHash {
         // Generate hash, needs to be from known values so we can reproduce the hash without storing
-        let input = format!(
-            "{:?}{:?}{:?}{:?}",
-            item.contract_id, item.function_id, item.cadence, item.owner_id
-        );
-        env::sha256(input.as_bytes())
+        // Function calls keep the fields hashed before transfers existed, so their hashes don't change.
+        // Kind & deposit tell apart transfers of different amounts to the same account
+        let input = match item.kind {
+            ActionKind::FunctionCall => format!(
+                "{:?}{:?}{:?}{:?}",
+                item.contract_id, item.function_id, item.cadence, item.owner_id
+            ),
+            ActionKind::Transfer => format!(
+                "{:?}{:?}{:?}{:?}{:?}{:?}",
+                item.contract_id,
+                item.function_id,
+                item.cadence,
+                item.owner_id,
+                item.kind,
+                item.deposit.0
+            ),
+        };
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&env::sha256(input.as_bytes()));
+        TaskHash(hash)
     }
 
-    /// Returns the base amount required to execute 1 task
-    /// NOTE: this is not the final used amount, just the user-specified amount total needed
-    fn task_balance_uses(&self, task: &Task) -> u128 {
-        task.deposit.0 + (u128::from(task.gas) * self.gas_price) + self.agent_fee
+    /// Takes the remaining balance & accrued staking yield out of a task, returns the amount to refund
+    fn take_task_balance(&mut self, task: &mut Task) -> Balance {
+        self.settle_task_yield(task);
+        self.total_task_deposits = self.total_task_deposits.saturating_sub(task.total_deposit.0);
+        let balance = task.total_deposit.0 + task.accrued_yield.0;
+        task.total_deposit = U128::from(0);
+        task.accrued_yield = U128::from(0);
+        balance
     }
 
-    /// Check if a cadence string is valid by attempting to parse it
-    fn validate_cadence(&self, cadence: &str) -> bool {
-        let s = Schedule::from_str(&cadence);
-        if s.is_ok() {
-            true
-        } else {
-            false
+    /// Whether the call a callback follows failed
+    /// NOTE: Only available in a callback of the call
+    fn call_failed() -> bool {
+        !matches!(env::promise_result(0), PromiseResult::Successful(_))
+    }
+
+    /// Credits deposits of calls that failed or were never made back to the task balance,
+    /// or to the payer once the task exited
+    fn refund_task_deposit(&mut self, task_hash: &TaskHash, payer_id: &AccountId, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        match self.tasks.get(task_hash) {
+            Some(mut task) => {
+                self.settle_task_yield(&mut task);
+                task.total_deposit = U128::from(task.total_deposit.0 + amount);
+                self.total_task_deposits += amount;
+                self.tasks.insert(task_hash, &task);
+            }
+            None => {
+                Promise::new(payer_id.clone()).transfer(amount);
+            }
         }
     }
-}
 
-#[cfg(all(test, not(target_arch = "wasm32")))]
-mod tests {
-    use super::*;
-    use near_sdk::json_types::ValidAccountId;
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::{testing_env, MockedBlockchain};
+    /// Calls the balance notification of a task once the executions left fall below its threshold
+    /// Executions left are based on the cost of the current execution
+    fn notify_low_balance(
+        &self,
+        task_hash: &TaskHash,
+        task: &mut Task,
+        balance: Balance,
+        call_balance: Balance,
+    ) {
+        let notify = match task.balance_notify.as_mut() {
+            Some(notify) => notify,
+            None => return,
+        };
+        let executions_left = balance.checked_div(call_balance).unwrap_or(u128::MAX);
+        let low = executions_left < u128::from(notify.threshold);
+        if low && !notify.notified {
+            log_event(
+                "task_balance_low",
+                json!({ "task_hash": task_hash, "balance": U128::from(balance) }),
+            );
+            env::promise_create(
+                notify.contract_id.clone(),
+                notify.function_id.as_bytes(),
+                json!({
+                    "task_hash": task_hash,
+                    "balance": U128::from(balance)
+                }).to_string().as_bytes(),
+                0,
+                GAS_FOR_BALANCE_NOTIFY,
+            );
+        }
+        notify.notified = low;
+    }
+
+    /// Creates the promise executing a task, or task action, returns the promise index
+    /// Transfers are created the same way as `Promise::transfer`, as the index is needed to chain them
+    fn create_action_promise(
+        kind: ActionKind,
+        contract_id: AccountId,
+        function_id: &str,
+        arguments: &Base64VecU8,
+        deposit: Balance,
+        gas: Gas,
+    ) -> u64 {
+        match kind {
+            ActionKind::FunctionCall => env::promise_create(
+                contract_id,
+                function_id.as_bytes(),
+                arguments.0.as_slice(),
+                deposit,
+                gas,
+            ),
+            ActionKind::Transfer => {
+                let promise = env::promise_batch_create(contract_id);
+                env::promise_batch_action_transfer(promise, deposit);
+                promise
+            }
+        }
+    }
+
+    /// Gas attached to `callback_for_task_action`, covering the remaining actions & their callbacks
+    fn task_action_callback_gas(actions: &[TaskAction], report: bool) -> Gas {
+        let done_gas = if report {
+            GAS_FOR_CALLBACK + GAS_FOR_ON_RESULT
+        } else {
+            GAS_FOR_CALLBACK
+        };
+        actions
+            .iter()
+            .fold(done_gas, |gas, action| gas + action.gas + GAS_FOR_CALLBACK)
+    }
+
+    /// Calls the `on_result` callback of a task with the outcome of the execution promise
+    /// NOTE: Only available in a callback of the execution promise
+    fn report_task_result(&self, task_hash: &TaskHash, execution_index: u64, on_result: &ResultCallback) {
+        let (success, result) = match env::promise_result(0) {
+            PromiseResult::Successful(value) => (true, Some(Base64VecU8::from(value))),
+            _ => (false, None),
+        };
+        let data = json!({
+            "task_hash": task_hash,
+            "execution_index": U64::from(execution_index),
+            "success": success,
+            "result": result
+        });
+        log_event("task_result", data.clone());
+        env::promise_create(
+            on_result.contract_id.clone(),
+            on_result.function_id.as_bytes(),
+            data.to_string().as_bytes(),
+            0,
+            GAS_FOR_ON_RESULT,
+        );
+    }
+
+    /// Adds a task to the catalog & schedules its first run
+    /// Returns the task hash
+    pub(crate) fn insert_task(&mut self, mut item: Task) -> TaskHash {
+        let hash = self.hash(&item);
+        // log!("Task Hash (as bytes) {:?}", &hash);
+
+        assert!(self.tasks.get(&hash).is_none(), "Task already exists");
+        assert!(self.reslot_cursor.is_none(), "Re-slotting tasks, catalog changes paused");
+
+        // Parse cadence into a future timestamp, then convert to a slot
+        item.next_ts = U64::from(self.get_next_timestamp(&item.cadence, env::block_timestamp()));
+        let next_slot = self.get_slot_from_timestamp(item.next_ts.0);
+        let next_slot = self.apply_jitter(&hash, &item, next_slot);
+        item.next_slot = U128::from(self.schedule_task(&hash, &item, next_slot));
+        log!("Task next slot: {}", item.next_slot.0);
+
+        // Add task to catalog
+        self.tasks.insert(&hash, &item);
+        self.total_task_deposits += item.total_deposit.0;
+
+        hash
+    }
+
+    /// Schedules the following run of a recurring task
+    /// The following run is computed from the intended fire timestamp, so execution delays don't add up
+    /// Pending catch-up runs go in the slot right after `current_slot`,
+    /// otherwise the task continues from the next future run
+    fn reschedule_task(&mut self, task_hash: &TaskHash, mut task: Task, current_slot: u128) {
+        let current_block_ts = env::block_timestamp();
+        let mut next_ts = self.get_next_timestamp(&task.cadence, task.next_ts.0);
+        let next_slot = if task.catch_up_remaining > 0 {
+            task.catch_up_remaining -= 1;
+            current_slot + self.slot_width()
+        } else {
+            if next_ts <= current_block_ts {
+                next_ts = self.get_next_timestamp(&task.cadence, current_block_ts);
+            }
+
+            // Never schedule in the current slot again, or in the past
+            let next_slot = core::cmp::max(
+                self.get_slot_from_timestamp(next_ts),
+                current_slot + self.slot_width(),
+            );
+            self.apply_jitter(task_hash, &task, next_slot)
+        };
+
+        // Add to the next slot with room left
+        task.next_ts = U64::from(next_ts);
+        task.next_slot = U128::from(self.schedule_task(task_hash, &task, next_slot));
+        log!("Scheduling Next Task {:?}", &task.next_slot.0);
+        self.tasks.insert(task_hash, &task);
+    }
+
+    /// Pops the next due task hash, along with its slot & the agent fee for its call
+    /// priority goes to tasks that have fallen behind (using floor key).
+    /// The manager's own tick task is paid by the treasury here, an unfunded tick is rescheduled
+    /// & the following due task is popped instead. Returns None once only unfunded ticks were due.
+    fn pop_due_task(&mut self, current_slot: u128) -> Option<(TaskHash, u128, Balance)> {
+        loop {
+            let slot_ballpark = if let Some(k) = self.slots.floor_key(&current_slot) {
+                k
+            } else {
+                env::log(b"aloha ow my brain");
+                current_slot
+            };
+            assert!(self.slots.get(&slot_ballpark).is_some(), "No tasks found in slot");
+
+            // Agent fee depends on the backlog, so compute it before the slot may get cleaned up
+            let agent_fee = self.get_effective_agent_fee();
+
+            // Pop the first task hash based on the slot ordering policy
+            let hash = self.slot_pop(slot_ballpark).expect("No tasks available");
+            if hash != self.tick_task_hash {
+                return Some((hash, slot_ballpark, agent_fee));
+            }
+
+            let task = self.tasks.get(&hash).expect("No task found by hash");
+            let call_total_balance = task.total_call_deposit()
+                + u128::from(task.charged_gas()) * self.gas_price
+                + agent_fee
+                + task.tip.0;
+            if self.pay_tick(call_total_balance) {
+                return Some((hash, slot_ballpark, agent_fee));
+            }
+            log!("Not enough treasury balance to execute tick, skipping");
+            self.reschedule_task(&hash, task, current_slot);
+            if self.slots.floor_key(&current_slot).is_none() {
+                return None;
+            }
+        }
+    }
+
+    /// Slot a callback reschedules its task from, the slot the call was made in
+    /// The slot mode may change while the call is in flight, a slot ahead of the current one comes
+    /// from the previous mode & is replaced by the current slot
+    fn in_flight_slot(&self, current_slot: u128) -> u128 {
+        core::cmp::min(current_slot, self.get_slot_id(None))
+    }
+
+    /// Delays a slot by a deterministic, hash derived number of slots within the task's jitter window
+    /// The delay always stays short of the following cadence occurrence, so no execution is skipped
+    fn apply_jitter(&self, task_hash: &TaskHash, task: &Task, slot: u128) -> u128 {
+        let granularity = core::cmp::max(self.slot_granularity, 1);
+        let mut steps = task.jitter.unwrap_or(self.slot_jitter) / granularity;
+        if steps == 0 {
+            return slot;
+        }
+
+        // Bound the window by the cadence interval, converted to blocks or seconds
+        let schedule = Schedule::from_str(&task.cadence).unwrap();
+        if let Some(next_ts) = schedule.next_after(&env::block_timestamp()) {
+            if let Some(following_ts) = schedule.next_after(&next_ts) {
+                let interval = match self.slot_mode {
+                    SlotMode::Block => {
+                        ((following_ts - next_ts) as u128 * self.get_bps() as u128)
+                            / BPS_DENOMINATOR as u128
+                            / NANO as u128
+                    }
+                    SlotMode::Timestamp => ((following_ts - next_ts) / NANO) as u128,
+                };
+                let max_steps = (interval as u64 / granularity).saturating_sub(1);
+                steps = core::cmp::min(steps, max_steps);
+            }
+        }
+
+        let mut seed = [0u8; 8];
+        seed.copy_from_slice(&task_hash.0[..8]);
+        let offset = u64::from_le_bytes(seed) % (steps + 1);
+        slot + u128::from(offset) * self.slot_width()
+    }
+
+    /// Adds a task hash to the first slot at or after `slot` that still has room, see `slot_capacity`
+    /// Returns the slot the task hash was added to
+    fn schedule_task(&mut self, task_hash: &TaskHash, task: &Task, slot: u128) -> u128 {
+        let mut next_slot = slot;
+        let slot_len = |s: &Self, slot: u128| s.slots.get(&slot).map(|q| q.len()).unwrap_or(0);
+        let mut overflow = 0;
+        while self.slot_capacity > 0 && slot_len(self, next_slot) >= self.slot_capacity {
+            overflow += 1;
+            assert!(overflow <= MAX_SLOT_OVERFLOW, "No slot with capacity available");
+            next_slot += self.slot_width();
+        }
+        if next_slot != slot {
+            log!("Slot {} at capacity, task overflows into slot {}", slot, next_slot);
+        }
+
+        self.slot_push(next_slot, task_hash, task);
+        next_slot
+    }
+
+    /// Task hashes of a slot, in execution order
+    pub(crate) fn slot_hashes(&self, slot: u128) -> Vec<TaskHash> {
+        let queue = self.slots.get(&slot).unwrap_or_default();
+        queue
+            .lanes
+            .iter()
+            .flat_map(|lane| (lane.head..lane.tail).map(move |index| (lane.key, index)))
+            .filter_map(|(key, index)| self.slot_entries.get(&(slot, key, index)))
+            .collect()
+    }
+
+    /// Adds a task hash at the tail of its lane, without reading or rewriting the other hashes in the slot
+    /// Lanes are sorted by order key, so the slot stays sorted by `slot_ordering`
+    fn slot_push(&mut self, slot: u128, task_hash: &TaskHash, task: &Task) {
+        let mut queue = self.slots.get(&slot).unwrap_or_default();
+        let key = self.slot_order_key(task);
+        let position = match queue.lanes.binary_search_by_key(&key, |lane| lane.key) {
+            Ok(position) => position,
+            Err(position) => {
+                queue.lanes.insert(position, SlotLane { key, head: 0, tail: 0, len: 0 });
+                position
+            }
+        };
+        let lane = &mut queue.lanes[position];
+        let index = lane.tail;
+        lane.tail += 1;
+        lane.len += 1;
+        self.slot_entries.insert(&(slot, key, index), task_hash);
+        self.slots.insert(&slot, &queue);
+        self.slot_keys.insert(task_hash, &(key, index));
+    }
+
+    /// Takes the task hash at the head of the first lane out of a slot, cleaning up the slot once empty
+    /// Entries removed out of order are skipped over
+    fn slot_pop(&mut self, slot: u128) -> Option<TaskHash> {
+        let mut queue = self.slots.get(&slot)?;
+        let lane = queue.lanes.first_mut()?;
+        let mut task_hash = None;
+        while task_hash.is_none() && lane.head < lane.tail {
+            task_hash = self.slot_entries.remove(&(slot, lane.key, lane.head));
+            lane.head += 1;
+        }
+        let task_hash = task_hash?;
+        lane.len -= 1;
+        if lane.len == 0 {
+            queue.lanes.remove(0);
+        }
+        self.slot_keys.remove(&task_hash);
+        self.save_slot(slot, &queue);
+        Some(task_hash)
+    }
+
+    /// Removes a task hash from a slot, returns false if the slot doesn't hold it
+    fn slot_remove(&mut self, slot: u128, task_hash: &TaskHash) -> bool {
+        let (key, index) = match self.slot_keys.get(task_hash) {
+            Some(key) => key,
+            None => return false,
+        };
+        let mut queue = match self.slots.get(&slot) {
+            Some(queue) => queue,
+            None => return false,
+        };
+        if self.slot_entries.remove(&(slot, key, index)).is_none() {
+            return false;
+        }
+        let position = queue
+            .lanes
+            .binary_search_by_key(&key, |lane| lane.key)
+            .expect("No lane found by key");
+        let lane = &mut queue.lanes[position];
+        lane.len -= 1;
+        if lane.len == 0 {
+            queue.lanes.remove(position);
+        } else if index == lane.head {
+            lane.head += 1;
+        }
+        self.slot_keys.remove(task_hash);
+        self.save_slot(slot, &queue);
+        true
+    }
+
+    /// Writes back a slot after a change, removing it once empty
+    fn save_slot(&mut self, slot: u128, queue: &SlotQueue) {
+        if queue.is_empty() {
+            self.slots.remove(&slot);
+        } else {
+            self.slots.insert(&slot, queue);
+        }
+    }
+
+    /// Moves a pending task into the slot matching the current slot settings
+    /// Tasks currently executing are not in a slot, their callback schedules them
+    pub(crate) fn reslot_task(&mut self, task_hash: &TaskHash) {
+        let mut task = self.tasks.get(task_hash).expect("No task found by hash");
+        if !self.slot_remove(task.next_slot.0, task_hash) {
+            return;
+        }
+
+        let next_slot = self.get_slot_from_timestamp(task.next_ts.0);
+        let next_slot = self.apply_jitter(task_hash, &task, next_slot);
+        task.next_slot = U128::from(self.schedule_task(task_hash, &task, next_slot));
+        self.tasks.insert(task_hash, &task);
+    }
+
+    /// Returns the base amount required to execute 1 task
+    /// NOTE: this is not the final used amount, just the user-specified amount total needed
+    pub(crate) fn task_balance_uses(&self, task: &Task) -> u128 {
+        task.total_call_deposit()
+            + (u128::from(task.charged_gas()) * self.gas_price)
+            + self.get_effective_agent_fee()
+            + task.tip.0
+    }
+
+    /// Agent fee scaled by how far execution has fallen behind
+    /// `agent_fee` applies while slots are on time, increasing linearly up to `agent_fee_max`
+    /// once the oldest pending slot is `agent_fee_backlog` slots behind the current slot
+    pub(crate) fn get_effective_agent_fee(&self) -> Balance {
+        if self.agent_fee_max <= self.agent_fee || self.agent_fee_backlog == 0 {
+            return self.agent_fee;
+        }
+
+        let current_slot = self.get_slot_id(None);
+        let backlog = match self.slots.min() {
+            Some(oldest) if oldest < current_slot => {
+                (current_slot - oldest) / self.slot_width()
+            }
+            _ => 0,
+        };
+        let depth = core::cmp::min(backlog, u128::from(self.agent_fee_backlog));
+
+        self.agent_fee
+            + (self.agent_fee_max - self.agent_fee) * depth / u128::from(self.agent_fee_backlog)
+    }
+
+    /// Sort key of a task within a slot, based on `slot_ordering`
+    /// The lowest key is executed first, equal keys keep their slot insertion order
+    /// NOTE: Keys are fixed once slotted, so changing `slot_ordering` re-slots all tasks
+    pub(crate) fn slot_order_key(&self, task: &Task) -> u128 {
+        match self.slot_ordering {
+            SlotOrdering::Fifo => 0,
+            SlotOrdering::Priority => u128::MAX - task.tip.0,
+            SlotOrdering::CreatedAt => u128::from(task.created_at.0),
+        }
+    }
+
+    /// Check if a cadence string is valid by attempting to parse it
+    pub(crate) fn validate_cadence(&self, cadence: &str) -> bool {
+        let s = Schedule::from_str(&cadence);
+        if s.is_ok() {
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+pub(crate) mod tests {
+    use super::*;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::serde_json::Value;
+    use near_sdk::test_utils::{
+        accounts, get_logs, testing_env_with_promise_results, VMContextBuilder,
+    };
+    use near_sdk::{testing_env, MockedBlockchain};
 
     use chrono::prelude::DateTime;
     use chrono::Utc;
@@ -331,18 +1484,41 @@ mod tests {
 
     const BLOCK_START_BLOCK: u64 = 52_201_040;
     const BLOCK_START_TS: u64 = 1_624_151_503_447_000_000;
+    const STORAGE_DEPOSIT: u128 = 10_000_000_000_000_000_000_000; // 0.01 Ⓝ, covers the task storage
 
     pub fn get_sample_task() -> Task {
         Task {
             owner_id: String::from("bob"),
             contract_id: String::from("danny"),
             function_id: String::from("increment"),
+            kind: ActionKind::FunctionCall,
             cadence: String::from("0 0 */1 * * *"),
             recurring: false,
             total_deposit: U128::from(1000000000020000000100),
+            storage_deposit: U128::from(0),
+            storage_prepaid: false,
+            credit_cap: None,
+            credit_used: U128::from(0),
+            sponsor_id: None,
+            approved_sponsor_id: None,
+            owner_deposit: U128::from(0),
+            balance_notify: None,
+            on_result: None,
             deposit: U128::from(100),
             gas: 200,
             arguments: Base64VecU8::from(vec![]),
+            actions: vec![],
+            tip: U128::from(0),
+            created_at: U64::from(BLOCK_START_TS),
+            jitter: None,
+            catch_up: CatchUpPolicy::Once,
+            catch_up_remaining: 0,
+            skipped_runs: 0,
+            executions: 0,
+            next_ts: U64::from(1624154400 * NANO),
+            next_slot: U128::from(52203900),
+            yield_checkpoint: U128::from(0),
+            accrued_yield: U128::from(0),
         }
     }
 
@@ -389,23 +1565,105 @@ mod tests {
         assert!(contract.get_all_tasks(None).is_empty());
         testing_env!(context
             .is_view(false)
-            .attached_deposit(1000000000020000000100)
+            .attached_deposit(1000000000020000000100 + STORAGE_DEPOSIT)
             .build());
         let task_id = contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "0 0 */1 * * *".to_string(),
             Some(false),
             Some(U128::from(100)),
             Some(200),
             None,
+            None,
         );
 
         testing_env!(context.is_view(true).build());
         assert_eq!(contract.get_all_tasks(None).len(), 1);
 
-        let daily_task = get_sample_task();
-        assert_eq!(contract.get_task(task_id), daily_task);
+        // Storage is reserved from the deposit, the rest funds executions
+        let task = contract.get_task(task_id);
+        assert!(task.storage_deposit.0 > 0);
+        assert_eq!(
+            task.total_deposit.0 + task.storage_deposit.0,
+            1000000000020000000100 + STORAGE_DEPOSIT
+        );
+        let daily_task = Task {
+            total_deposit: task.total_deposit.clone(),
+            storage_deposit: task.storage_deposit.clone(),
+            ..get_sample_task()
+        };
+        assert_eq!(task, daily_task);
+    }
+
+    #[test]
+    fn test_task_create_storage() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        let initial_storage_usage = env::storage_usage();
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 0 */1 * * *".to_string(),
+            Some(true),
+            Some(U128::from(100)),
+            Some(200),
+            Some(Base64VecU8::from(vec![1; 100])),
+            None,
+        );
+        let storage_cost = Balance::from(env::storage_usage() - initial_storage_usage)
+            * env::storage_byte_cost();
+
+        let task = contract.get_task(task_hash.clone());
+        assert_eq!(task.storage_deposit.0, storage_cost);
+        assert_eq!(task.total_deposit.0, ONE_NEAR - storage_cost);
+        assert_eq!(contract.total_task_deposits, ONE_NEAR - storage_cost);
+
+        // Storage is released along with the task
+        contract.remove_task(task_hash);
+        assert_eq!(env::storage_usage(), initial_storage_usage);
+        assert_eq!(contract.total_task_deposits, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough task balance to cover storage")]
+    fn test_task_create_storage_not_enuf() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(1000000000020000000100).build());
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 0 */1 * * *".to_string(),
+            Some(false),
+            Some(U128::from(100)),
+            Some(200),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Arguments exceed the max size of 4 bytes")]
+    fn test_task_create_arguments_too_large() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.max_arguments_size = 4;
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 0 */1 * * *".to_string(),
+            Some(false),
+            Some(U128::from(100)),
+            Some(200),
+            Some(Base64VecU8::from(vec![1; 5])),
+            None,
+        );
     }
 
     #[test]
@@ -415,19 +1673,23 @@ mod tests {
         testing_env!(context.build());
         let mut contract = Contract::new();
         testing_env!(context.is_view(false).build());
-        contract.update_settings(None, None, Some(true), None, None, None);
+        contract.apply_settings(SettingsUpdate {
+            paused: Some(true),
+            ..Default::default()
+        });
         testing_env!(context
             .is_view(false)
-            .attached_deposit(1000000000020000000100)
+            .attached_deposit(1000000000020000000100 + STORAGE_DEPOSIT)
             .build());
         contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "0 0 */1 * * *".to_string(),
             Some(true),
             Some(U128::from(100)),
             Some(200),
             None,
+            None,
         );
     }
 
@@ -439,16 +1701,17 @@ mod tests {
         let mut contract = Contract::new();
         testing_env!(context
             .is_view(false)
-            .attached_deposit(1000000000020000000100)
+            .attached_deposit(1000000000020000000100 + STORAGE_DEPOSIT)
             .build());
         contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "raspberry_oat_milk".to_string(),
             Some(true),
             Some(U128::from(100)),
             Some(200),
             None,
+            None,
         );
     }
 
@@ -463,12 +1726,13 @@ mod tests {
         testing_env!(context.is_view(false).attached_deposit(0).build());
         contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "0 0 */1 * * *".to_string(),
             Some(false),
             Some(U128::from(100000)),
             Some(200),
             None,
+            None,
         );
     }
 
@@ -483,12 +1747,13 @@ mod tests {
         testing_env!(context.is_view(false).attached_deposit(0).build());
         contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "0 0 */1 * * *".to_string(),
             Some(true),
             Some(U128::from(100000)),
             Some(200),
             None,
+            None,
         );
     }
 
@@ -520,27 +1785,26 @@ mod tests {
         // Move forward time and blocks to get more accurate bps
         testing_env!(context
             .is_view(false)
-            .attached_deposit(1000000000020000000100)
+            .attached_deposit(1000000000020000000100 + STORAGE_DEPOSIT)
             .block_timestamp(BLOCK_START_TS + (6 * NANO))
             .block_index(BLOCK_START_BLOCK + 6)
             .build());
 
         contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "*/10 * * * * *".to_string(),
             Some(false),
             Some(U128::from(0)),
             Some(200),
             None,
+            None,
         );
         testing_env!(context.is_view(true).build());
-        let slot = contract
-            .slots
-            .get(&52201080)
-            .expect("Should have something here");
+        let slot = contract.slot_hashes(52201080);
+        assert!(!slot.is_empty(), "Should have something here");
         assert_eq!(
-            slot[0],
+            slot[0].0,
             [21, 209, 124, 71, 241, 6, 3, 102, 114, 186, 60, 89, 64, 69, 99, 43, 141, 4, 101, 196, 41, 133, 9, 73, 102, 127, 6, 197, 80, 247, 8, 116]
         );
     }
@@ -554,7 +1818,7 @@ mod tests {
         // Move forward time and blocks to get more accurate bps
         testing_env!(context
             .is_view(false)
-            .attached_deposit(1000000000020000000100)
+            .attached_deposit(1000000000020000000100 + STORAGE_DEPOSIT)
             .block_timestamp(BLOCK_START_TS + (6 * NANO))
             .block_index(BLOCK_START_BLOCK + 6)
             .build());
@@ -562,21 +1826,23 @@ mod tests {
         // create a some tasks
         contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "*/10 * * * * *".to_string(),
             Some(false),
             Some(U128::from(0)),
             Some(200),
             None,
+            None,
         );
         contract.create_task(
             accounts(3),
-            "decrement".to_string(),
+            Some("decrement".to_string()),
             "*/10 * * * * *".to_string(),
             Some(false),
             Some(U128::from(0)),
             Some(200),
             None,
+            None,
         );
         testing_env!(context
             .is_view(false)
@@ -634,7 +1900,7 @@ mod tests {
         let context = get_context(accounts(1));
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.callback_for_proxy_call(vec![0, 1, 2, 3], U128::from(123400));
+        contract.callback_for_proxy_call(TaskHash([0; 32]), U128::from(123400));
     }
 
     #[test]
@@ -645,16 +1911,17 @@ mod tests {
         let mut contract = Contract::new();
         testing_env!(context
             .is_view(false)
-            .attached_deposit(1000000000020000000100)
+            .attached_deposit(1000000000020000000100 + STORAGE_DEPOSIT)
             .build());
         contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "0 0 */1 * * *".to_string(),
             Some(false),
             Some(U128::from(100)),
             Some(200),
             None,
+            None,
         );
         testing_env!(context
             .is_view(false)
@@ -673,18 +1940,22 @@ mod tests {
         let mut contract = Contract::new();
         testing_env!(context
             .is_view(false)
-            .attached_deposit(1000000000020000000100)
+            .attached_deposit(1000000000020000000100 + STORAGE_DEPOSIT)
             .build());
         contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "0 0 */1 * * *".to_string(),
             Some(false),
             Some(U128::from(100)),
             Some(200),
             None,
+            None,
         );
-        contract.update_settings(None, None, Some(true), None, None, None);
+        contract.apply_settings(SettingsUpdate {
+            paused: Some(true),
+            ..Default::default()
+        });
         testing_env!(context.is_view(false).block_index(1260).build());
         contract.proxy_call();
     }
@@ -715,12 +1986,13 @@ mod tests {
             .build());
         let task_hash = contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "0 0 */1 * * *".to_string(),
             Some(false),
             Some(U128::from(100)),
             Some(200),
             None,
+            None,
         );
 
         testing_env!(context.is_view(true).build());
@@ -743,16 +2015,17 @@ mod tests {
         assert!(contract.get_all_tasks(None).is_empty());
         testing_env!(context
             .is_view(false)
-            .attached_deposit(1000000000020000000100)
+            .attached_deposit(1000000000020000000100 + STORAGE_DEPOSIT)
             .build());
         let task_hash = contract.create_task(
             accounts(3),
-            "increment".to_string(),
+            Some("increment".to_string()),
             "0 0 */1 * * *".to_string(),
             Some(false),
             Some(U128::from(100)),
             Some(200),
             None,
+            None,
         );
 
         testing_env!(context.is_view(true).build());
@@ -772,102 +2045,990 @@ mod tests {
         let context = get_context(accounts(1));
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.remove_task(Base64VecU8::from(vec![0, 1, 2, 3]));
+        contract.remove_task(TaskHash([0; 32]));
+    }
+
+    fn create_sponsored_task(contract: &mut Contract, context: &mut VMContextBuilder) -> TaskHash {
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        testing_env!(context.attached_deposit(0).build());
+        contract.approve_sponsor(task_hash.clone(), Some(accounts(2)));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_NEAR * 2)
+            .build());
+        contract.sponsor_task(task_hash.clone());
+        task_hash
     }
 
     #[test]
-    fn test_get_slot_id_current_block() {
+    fn test_task_sponsor() {
         let mut context = get_context(accounts(1));
         testing_env!(context.build());
-        let contract = Contract::new();
-        testing_env!(context.is_view(true).build());
-        let slot = contract.get_slot_id(None);
-
-        assert_eq!(slot, 52201020);
+        let mut contract = Contract::new();
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
+
+        let task = contract.get_task(task_hash.clone());
+        assert_eq!(task.sponsor_id, Some(accounts(2).to_string()));
+        assert_eq!(task.payer_id(), &accounts(2).to_string());
+        assert_eq!(task.total_deposit.0, ONE_NEAR * 2);
+        assert_eq!(contract.total_task_deposits, ONE_NEAR * 2);
+        // The owner balance is set aside, not refunded
+        assert!(task.owner_deposit.0 > 0);
+
+        // The owner keeps remove rights
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.remove_task(task_hash);
+        assert_eq!(contract.total_task_deposits, 0);
     }
 
     #[test]
-    fn test_get_slot_id_offset_block() {
+    #[should_panic(expected = "Task already sponsored")]
+    fn test_task_sponsor_twice() {
         let mut context = get_context(accounts(1));
         testing_env!(context.build());
-        let contract = Contract::new();
-        testing_env!(context.is_view(true).build());
-        let slot = contract.get_slot_id(Some(1_000));
+        let mut contract = Contract::new();
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
 
-        assert_eq!(slot, 52201980);
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.sponsor_task(task_hash);
     }
 
     #[test]
-    fn test_get_slot_id_max_block() {
+    #[should_panic(expected = "Sponsor not approved by the task owner")]
+    fn test_task_sponsor_not_approved() {
         let mut context = get_context(accounts(1));
         testing_env!(context.build());
-        let contract = Contract::new();
-        testing_env!(context.is_view(true).build());
-        let slot = contract.get_slot_id(Some(1_000_000_000_000));
+        let mut contract = Contract::new();
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.revoke_sponsorship(task_hash.clone());
 
-        // ensure even if we pass in a HUGE number, it can only be scheduled UP to the max pre-defined block settings
-        assert_eq!(slot, 1_000_052_200_980);
+        // Approval covers a single sponsor
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        contract.sponsor_task(task_hash);
     }
 
     #[test]
-    fn test_get_slot_id_change_granularity() {
+    #[should_panic(expected = "Only owner can update their task.")]
+    fn test_task_approve_sponsor_not_owner() {
         let mut context = get_context(accounts(1));
         testing_env!(context.build());
         let mut contract = Contract::new();
-        testing_env!(context.is_view(true).build());
-        let slot = contract.get_slot_id(None);
-        assert_eq!(slot, 52201020);
-
-        testing_env!(context.is_view(false).build());
-        contract.update_settings(None, Some(10), None, None, None, None);
-        testing_env!(context.is_view(true).build());
-        let slot = contract.get_slot_id(None);
-        assert_eq!(slot, 52201040);
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
 
-        testing_env!(context.is_view(false).build());
-        contract.update_settings(None, Some(1), None, None, None, None);
-        testing_env!(context.is_view(true).build());
-        let slot = contract.get_slot_id(None);
-        assert_eq!(slot, 52201040);
+        // A sponsor can't approve another one
+        contract.approve_sponsor(task_hash, Some(accounts(4)));
     }
 
     #[test]
-    fn test_get_slot_from_cadence_ts_check() {
-        // let start_ts: u64 = 1_624_151_500_000_000_000;
-        let rem = BLOCK_START_TS.clone() % 1_000_000;
-        let secs = ((BLOCK_START_TS.clone() - rem) / 1_000_000_000) + 1;
-        let start_ts = Utc.timestamp(secs as i64, 0).naive_utc().timestamp_nanos() as u64;
-        let context = get_context(accounts(1));
+    #[should_panic(expected = "Not enough sponsor balance to execute task")]
+    fn test_task_sponsor_not_enuf() {
+        let mut context = get_context(accounts(1));
         testing_env!(context.build());
-        let current_block_ts = env::block_timestamp();
+        let mut contract = Contract::new();
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.revoke_sponsorship(task_hash.clone());
 
-        // Seconds
-        let schedule1 = Schedule::from_str(&"*/5 * * * * *".to_string()).unwrap();
-        let next_ts1 = schedule1.next_after(&current_block_ts).unwrap();
-        println!("TS 1: {} {}", next_ts1, human_readable_time(next_ts1));
-        let denom1 = 5 * NANO;
-        let rem1 = start_ts.clone() % denom1;
-        assert_eq!(next_ts1, (start_ts.clone() - rem1) + denom1);
+        testing_env!(context.attached_deposit(1).build());
+        contract.sponsor_task(task_hash);
+    }
 
-        // Minutes
-        let schedule2 = Schedule::from_str(&"* */5 * * * *".to_string()).unwrap();
-        let next_ts2 = schedule2.next_after(&current_block_ts).unwrap();
-        println!("TS 2: {} {}", next_ts2, human_readable_time(next_ts2));
-        let denom2 = 5 * 60 * NANO;
-        let rem2 = start_ts.clone() % denom2;
-        assert_eq!(next_ts2, (start_ts.clone() - rem2) + denom2);
+    #[test]
+    fn test_task_sponsor_credit() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_NEAR)
+            .build());
+        let credit = contract.deposit_credit(None).0;
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
+        testing_env!(context.attached_deposit(0).build());
+        contract.revoke_sponsorship(task_hash.clone());
 
-        // Hours
-        let schedule3 = Schedule::from_str(&"* * */5 * * *".to_string()).unwrap();
-        let next_ts3 = schedule3.next_after(&current_block_ts).unwrap();
-        println!("TS 3: {} {}", next_ts3, human_readable_time(next_ts3));
-        assert_eq!(next_ts3, 1624165200000000000);
+        // Sponsored by the credit balance alone
+        contract.sponsor_task(task_hash.clone());
+        assert_eq!(contract.get_task(task_hash.clone()).total_deposit.0, 0);
 
-        // Days
-        let schedule4 = Schedule::from_str(&"* * * 10 * *".to_string()).unwrap();
-        let next_ts4 = schedule4.next_after(&current_block_ts).unwrap();
-        println!("TS 4: {} {}", next_ts4, human_readable_time(next_ts4));
-        assert_eq!(next_ts4, 1625875200000000000);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        assert!(contract.tasks.get(&task_hash).is_none());
+        assert_eq!(
+            contract.get_credit_balance(accounts(2)).0,
+            credit - AGENT_BASE_FEE - 200 * GAS_BASE_PRICE
+        );
+    }
+
+    #[test]
+    fn test_task_sponsor_revoke() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
+        let owner_deposit = contract.get_task(task_hash.clone()).owner_deposit;
+
+        // The owner pays with the balance set aside again
+        testing_env!(context.attached_deposit(0).build());
+        contract.revoke_sponsorship(task_hash.clone());
+        let task = contract.get_task(task_hash);
+        assert_eq!(task.sponsor_id, None);
+        assert_eq!(task.total_deposit, owner_deposit);
+        assert_eq!(task.owner_deposit.0, 0);
+        assert_eq!(contract.total_task_deposits, owner_deposit.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the sponsor can revoke sponsorship")]
+    fn test_task_sponsor_revoke_not_sponsor() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.revoke_sponsorship(task_hash);
+    }
+
+    #[test]
+    fn test_task_balance_notify() {
+        // The notification gas is charged along with the task's own call
+        let call_balance = AGENT_BASE_FEE + (200 + u128::from(GAS_FOR_BALANCE_NOTIFY)) * GAS_BASE_PRICE;
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, Some(false));
+
+        testing_env!(context
+            .attached_deposit(call_balance * 3)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        testing_env!(context.attached_deposit(0).build());
+        contract.set_balance_notify(
+            task_hash.clone(),
+            Some(accounts(1)),
+            Some("refill".to_string()),
+            None,
+        );
+
+        // 2 executions left, below the default threshold
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        let notify = contract.get_task(task_hash.clone()).balance_notify.unwrap();
+        assert_eq!(notify.threshold, BALANCE_NOTIFY_THRESHOLD);
+        assert!(notify.notified);
+
+        testing_env!(context.attached_deposit(call_balance * 5).build());
+        contract.refill_task(task_hash.clone());
+        let mut context = get_context(accounts(0));
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (75 * NANO))
+            .block_index(BLOCK_START_BLOCK + 75)
+            .build());
+        contract.callback_for_proxy_call(task_hash.clone(), U128::from(52201080));
+
+        // Refilled above the threshold, notifies again once it runs low
+        let mut context = get_context(accounts(1));
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (125 * NANO))
+            .block_index(BLOCK_START_BLOCK + 125)
+            .build());
+        contract.proxy_call();
+        let task = contract.get_task(task_hash.clone());
+        assert_eq!(task.total_deposit.0, call_balance * 6);
+        assert!(!task.balance_notify.unwrap().notified);
+
+        // Runs low again after 4 more executions, notifying a second time
+        let mut block = BLOCK_START_BLOCK + 125;
+        for _ in 0..4 {
+            let mut context = get_context(accounts(0));
+            testing_env!(context
+                .block_timestamp(BLOCK_START_TS + (block - BLOCK_START_BLOCK) * NANO)
+                .block_index(block)
+                .build());
+            let current_slot = contract.get_slot_id(None);
+            contract.callback_for_proxy_call(task_hash.clone(), U128::from(current_slot));
+
+            block = contract.get_task(task_hash.clone()).next_slot.0 as u64 + 10;
+            let mut context = get_context(accounts(1));
+            testing_env!(context
+                .block_timestamp(BLOCK_START_TS + (block - BLOCK_START_BLOCK) * NANO)
+                .block_index(block)
+                .build());
+            contract.proxy_call();
+        }
+        let task = contract.get_task(task_hash);
+        assert_eq!(task.total_deposit.0, call_balance * 2);
+        assert!(task.balance_notify.unwrap().notified);
+        assert!(get_logs().iter().any(|log| log.contains("task_balance_low")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only owner can update their task.")]
+    fn test_task_balance_notify_not_owner() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
+
+        // The sponsor pays, but doesn't control the task
+        contract.set_balance_notify(task_hash, Some(accounts(2)), Some("refill".to_string()), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the task payer can refill the task")]
+    fn test_task_refill_not_payer() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.refill_task(task_hash);
+    }
+
+    #[test]
+    fn test_task_on_result() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        testing_env!(context.attached_deposit(0).build());
+        contract.set_on_result(
+            task_hash.clone(),
+            Some(accounts(1)),
+            Some("on_result".to_string()),
+        );
+        assert_eq!(
+            contract.get_task(task_hash.clone()).on_result,
+            Some(ResultCallback {
+                contract_id: accounts(1).to_string(),
+                function_id: "on_result".to_string(),
+            })
+        );
+        // The result callback & call are charged to the task
+        assert_eq!(
+            contract.get_task(task_hash.clone()).charged_gas(),
+            200 + GAS_FOR_CALLBACK + GAS_FOR_ON_RESULT
+        );
+
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        assert_eq!(contract.get_task(task_hash.clone()).executions, 1);
+
+        // Removing the callback
+        contract.set_on_result(task_hash.clone(), None, None);
+        assert_eq!(contract.get_task(task_hash).on_result, None);
+    }
+
+    /// Data of the latest `task_result` event
+    fn last_task_result() -> Value {
+        let log = get_logs()
+            .into_iter()
+            .rev()
+            .find(|log| log.contains("\"task_result\""))
+            .expect("No task result logged");
+        let event: Value =
+            near_sdk::serde_json::from_str(&log[crate::events::EVENT_JSON_PREFIX.len()..]).unwrap();
+        event["data"].clone()
+    }
+
+    fn create_on_result_task(contract: &mut Contract, context: &mut VMContextBuilder) -> TaskHash {
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        testing_env!(context.attached_deposit(0).build());
+        contract.set_on_result(
+            task_hash.clone(),
+            Some(accounts(1)),
+            Some("on_result".to_string()),
+        );
+        task_hash
+    }
+
+    #[test]
+    fn test_task_on_result_reported() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        let task_hash = create_on_result_task(&mut contract, &mut context);
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+
+        // Success, with the returned value
+        let context = get_context(accounts(0));
+        testing_env_with_promise_results(
+            context.build(),
+            PromiseResult::Successful(b"42".to_vec()),
+        );
+        contract.callback_for_proxy_call(task_hash.clone(), U128::from(52201080));
+        let result = last_task_result();
+        assert_eq!(result["task_hash"], json!(task_hash));
+        assert_eq!(result["execution_index"], "0");
+        assert_eq!(result["success"], true);
+        assert_eq!(result["result"], "NDI=");
+        assert!(contract.slot_keys.get(&task_hash).is_some(), "Rescheduled");
+
+        // The last execution of an exited task reports the same way
+        let on_result = contract.get_task(task_hash.clone()).on_result.unwrap();
+        testing_env_with_promise_results(
+            context.build(),
+            PromiseResult::Successful(b"43".to_vec()),
+        );
+        contract.callback_for_task_result(
+            task_hash.clone(),
+            accounts(1).to_string(),
+            U128::from(0),
+            U64::from(3),
+            Some(on_result.clone()),
+        );
+        let result = last_task_result();
+        assert_eq!(result["execution_index"], "3");
+        assert_eq!(result["success"], true);
+        assert_eq!(result["result"], "NDM=");
+
+        testing_env_with_promise_results(context.build(), PromiseResult::Failed);
+        contract.callback_for_task_result(
+            task_hash.clone(),
+            accounts(1).to_string(),
+            U128::from(0),
+            U64::from(4),
+            Some(on_result),
+        );
+        let result = last_task_result();
+        assert_eq!(result["task_hash"], json!(task_hash));
+        assert_eq!(result["execution_index"], "4");
+        assert_eq!(result["success"], false);
+        assert_eq!(result["result"], Value::Null);
+    }
+
+    #[test]
+    fn test_task_on_result_failed() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        let task_hash = create_on_result_task(&mut contract, &mut context);
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+
+        // A failed call is reported, the task is still rescheduled
+        let context = get_context(accounts(0));
+        testing_env_with_promise_results(context.build(), PromiseResult::Failed);
+        contract.callback_for_proxy_call(task_hash.clone(), U128::from(52201080));
+        let result = last_task_result();
+        assert_eq!(result["task_hash"], json!(task_hash));
+        assert_eq!(result["execution_index"], "0");
+        assert_eq!(result["success"], false);
+        assert_eq!(result["result"], Value::Null);
+        assert!(contract.slot_keys.get(&task_hash).is_some(), "Rescheduled");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only owner can update their task.")]
+    fn test_task_on_result_not_owner() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let task_hash = create_sponsored_task(&mut contract, &mut context);
+
+        contract.set_on_result(task_hash, Some(accounts(2)), Some("on_result".to_string()));
+    }
+
+    fn sample_action(function_id: &str) -> TaskAction {
+        TaskAction {
+            kind: ActionKind::FunctionCall,
+            contract_id: accounts(4).to_string(),
+            function_id: function_id.to_string(),
+            arguments: Base64VecU8::from(vec![]),
+            deposit: U128::from(50),
+            gas: 100,
+        }
+    }
+
+    #[test]
+    fn test_task_actions() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("close_bidding".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                actions: Some(vec![sample_action("transfer_nft"), sample_action("pay_artist")]),
+                ..Default::default()
+            }),
+        );
+        let task = contract.get_task(task_hash.clone());
+        assert_eq!(task.actions.len(), 2);
+        assert_eq!(task.total_gas(), 400);
+        assert_eq!(task.total_call_deposit(), 100);
+
+        // The agent is paid for the gas of all calls
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        assert!(contract.tasks.get(&task_hash).is_none());
+        assert_eq!(
+            contract.get_agent(accounts(1).to_string()).unwrap().balance.0,
+            2090000000000000000000 + AGENT_BASE_FEE + 400 * GAS_BASE_PRICE
+        );
+    }
+
+    fn create_actions_task(contract: &mut Contract, context: &mut VMContextBuilder) -> TaskHash {
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("close_bidding".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                actions: Some(vec![sample_action("transfer_nft"), sample_action("pay_artist")]),
+                ..Default::default()
+            }),
+        );
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        task_hash
+    }
+
+    #[test]
+    fn test_task_action_callback() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        let task_hash = create_actions_task(&mut contract, &mut context);
+        let balance = contract.get_task(task_hash.clone()).total_deposit.0;
+
+        // The previous call succeeded, the next action is called before rescheduling
+        let context = get_context(accounts(0));
+        testing_env_with_promise_results(context.build(), PromiseResult::Successful(vec![]));
+        contract.callback_for_task_action(
+            task_hash.clone(),
+            accounts(1).to_string(),
+            U128::from(0),
+            vec![sample_action("pay_artist")],
+            Some(U128::from(52201080)),
+            U64::from(0),
+            None,
+        );
+        assert!(contract.slot_keys.get(&task_hash).is_none());
+
+        // All actions done
+        testing_env_with_promise_results(context.build(), PromiseResult::Successful(vec![]));
+        contract.callback_for_task_action(
+            task_hash.clone(),
+            accounts(1).to_string(),
+            U128::from(0),
+            vec![],
+            Some(U128::from(52201080)),
+            U64::from(0),
+            None,
+        );
+        assert!(contract.slot_keys.get(&task_hash).is_some(), "Rescheduled");
+        assert_eq!(contract.get_task(task_hash).total_deposit.0, balance);
+    }
+
+    #[test]
+    fn test_task_action_callback_failed() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        let task_hash = create_actions_task(&mut contract, &mut context);
+        let balance = contract.get_task(task_hash.clone()).total_deposit.0;
+        let total_task_deposits = contract.total_task_deposits;
+
+        // The task's own call failed, both actions are skipped & their deposits credited back
+        let context = get_context(accounts(0));
+        testing_env_with_promise_results(context.build(), PromiseResult::Failed);
+        contract.callback_for_task_action(
+            task_hash.clone(),
+            accounts(1).to_string(),
+            U128::from(0),
+            vec![sample_action("transfer_nft"), sample_action("pay_artist")],
+            Some(U128::from(52201080)),
+            U64::from(0),
+            None,
+        );
+        assert!(contract.slot_keys.get(&task_hash).is_some(), "Rescheduled");
+        assert_eq!(contract.get_task(task_hash.clone()).total_deposit.0, balance + 100);
+        assert_eq!(contract.total_task_deposits, total_task_deposits + 100);
+
+        // Once the task exited, the deposits go back to the payer instead
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        contract.remove_task(task_hash.clone());
+        let context = get_context(accounts(0));
+        testing_env_with_promise_results(context.build(), PromiseResult::Failed);
+        contract.callback_for_task_action(
+            task_hash,
+            accounts(1).to_string(),
+            U128::from(0),
+            vec![sample_action("pay_artist")],
+            None,
+            U64::from(1),
+            None,
+        );
+        assert_eq!(contract.total_task_deposits, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Gas of the task, its actions & callbacks exceeds the max of")]
+    fn test_task_actions_gas_budget() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 0 */1 * * *".to_string(),
+            Some(false),
+            Some(U128::from(100)),
+            Some(250_000_000_000_000),
+            None,
+            Some(TaskOptions {
+                actions: Some(vec![sample_action("transfer_nft")]),
+                ..Default::default()
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough task balance to execute job")]
+    fn test_task_actions_deposit_not_enuf() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+
+        // Covers the task's own call, but not the following action
+        testing_env!(context.attached_deposit(1000000000020000000100).build());
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 0 */1 * * *".to_string(),
+            Some(false),
+            Some(U128::from(100)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                actions: Some(vec![sample_action("transfer_nft")]),
+                ..Default::default()
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many actions, the max is 5")]
+    fn test_task_actions_too_many() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 0 */1 * * *".to_string(),
+            Some(false),
+            Some(U128::from(100)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                actions: Some(vec![sample_action("transfer_nft"); 6]),
+                ..Default::default()
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Arguments exceed the max size of 4096 bytes")]
+    fn test_task_actions_arguments_too_large() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        let mut action = sample_action("transfer_nft");
+        action.arguments = Base64VecU8::from(vec![0; 4097]);
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 0 */1 * * *".to_string(),
+            Some(false),
+            Some(U128::from(100)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                actions: Some(vec![action]),
+                ..Default::default()
+            }),
+        );
+    }
+
+    #[test]
+    fn test_task_transfer() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let mut action = sample_action("");
+        action.kind = ActionKind::Transfer;
+        let task_hash = contract.create_task(
+            accounts(3),
+            None,
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(100)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                actions: Some(vec![action]),
+                kind: Some(ActionKind::Transfer),
+                ..Default::default()
+            }),
+        );
+
+        // The gas param is ignored, only the transfers are charged
+        let task = contract.get_task(task_hash.clone());
+        assert_eq!(task.kind, ActionKind::Transfer);
+        assert_eq!(task.gas, GAS_FOR_TRANSFER);
+        assert_eq!(task.actions[0].gas, GAS_FOR_TRANSFER);
+        assert_eq!(task.total_call_deposit(), 150);
+
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        assert!(contract.tasks.get(&task_hash).is_none());
+        assert_eq!(
+            contract.get_agent(accounts(1).to_string()).unwrap().balance.0,
+            2090000000000000000000
+                + AGENT_BASE_FEE
+                + u128::from(2 * GAS_FOR_TRANSFER) * GAS_BASE_PRICE
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Transfers need a deposit to send")]
+    fn test_task_transfer_no_deposit() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.create_task(
+            accounts(3),
+            None,
+            "0 0 */1 * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            None,
+            None,
+            Some(TaskOptions {
+                kind: Some(ActionKind::Transfer),
+                ..Default::default()
+            }),
+        );
+    }
+
+    fn create_transfer_task(contract: &mut Contract, deposit: u128) -> TaskHash {
+        contract.create_task(
+            accounts(3),
+            None,
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(deposit)),
+            None,
+            None,
+            Some(TaskOptions {
+                kind: Some(ActionKind::Transfer),
+                ..Default::default()
+            }),
+        )
+    }
+
+    #[test]
+    fn test_task_transfer_hash_by_deposit() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+
+        // Transfers need no function_id, the deposit tells them apart
+        let task_hash = create_transfer_task(&mut contract, 100);
+        let other_hash = create_transfer_task(&mut contract, 200);
+        assert_ne!(task_hash, other_hash);
+        assert_eq!(contract.get_task(task_hash).function_id, "");
+        assert_eq!(contract.get_task(other_hash).deposit.0, 200);
+    }
+
+    #[test]
+    #[should_panic(expected = "Function calls need a function_id")]
+    fn test_task_no_function_id() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.create_task(
+            accounts(3),
+            None,
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_task_transfer_failed_refund() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = create_transfer_task(&mut contract, 100);
+
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        let total_deposit = contract.get_task(task_hash.clone()).total_deposit.0;
+        let total_task_deposits = contract.total_task_deposits;
+
+        // The deposit of the failed transfer goes back to the task balance
+        let context = get_context(accounts(0));
+        testing_env_with_promise_results(context.build(), PromiseResult::Failed);
+        contract.callback_for_proxy_call(task_hash.clone(), U128::from(52201080));
+        let task = contract.get_task(task_hash.clone());
+        assert_eq!(task.total_deposit.0, total_deposit + 100);
+        assert_eq!(contract.total_task_deposits, total_task_deposits + 100);
+        assert!(contract.slot_keys.get(&task_hash).is_some(), "Rescheduled");
+    }
+
+    #[test]
+    fn test_get_slot_id_current_block() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let contract = Contract::new();
+        testing_env!(context.is_view(true).build());
+        let slot = contract.get_slot_id(None);
+
+        assert_eq!(slot, 52201020);
+    }
+
+    #[test]
+    fn test_get_slot_id_offset_block() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let contract = Contract::new();
+        testing_env!(context.is_view(true).build());
+        let slot = contract.get_slot_id(Some(1_000));
+
+        assert_eq!(slot, 52201980);
+    }
+
+    #[test]
+    fn test_get_slot_id_max_block() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let contract = Contract::new();
+        testing_env!(context.is_view(true).build());
+        let slot = contract.get_slot_id(Some(1_000_000_000_000));
+
+        // ensure even if we pass in a HUGE number, it can only be scheduled UP to the max pre-defined block settings
+        assert_eq!(slot, 1_000_052_200_980);
+    }
+
+    #[test]
+    fn test_get_slot_id_change_granularity() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.is_view(true).build());
+        let slot = contract.get_slot_id(None);
+        assert_eq!(slot, 52201020);
+
+        testing_env!(context.is_view(false).build());
+        contract.apply_settings(SettingsUpdate {
+            slot_granularity: Some(10),
+            ..Default::default()
+        });
+        testing_env!(context.is_view(true).build());
+        let slot = contract.get_slot_id(None);
+        assert_eq!(slot, 52201040);
+
+        testing_env!(context.is_view(false).build());
+        contract.apply_settings(SettingsUpdate {
+            slot_granularity: Some(1),
+            ..Default::default()
+        });
+        testing_env!(context.is_view(true).build());
+        let slot = contract.get_slot_id(None);
+        assert_eq!(slot, 52201040);
+    }
+
+    fn get_slot_from_cadence(contract: &Contract, cadence: &str) -> u128 {
+        let next_ts = contract.get_next_timestamp(&cadence.to_string(), env::block_timestamp());
+        contract.get_slot_from_timestamp(next_ts)
+    }
+
+    #[test]
+    fn test_get_slot_from_cadence_ts_check() {
+        // let start_ts: u64 = 1_624_151_500_000_000_000;
+        let rem = BLOCK_START_TS.clone() % 1_000_000;
+        let secs = ((BLOCK_START_TS.clone() - rem) / 1_000_000_000) + 1;
+        let start_ts = Utc.timestamp(secs as i64, 0).naive_utc().timestamp_nanos() as u64;
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let current_block_ts = env::block_timestamp();
+
+        // Seconds
+        let schedule1 = Schedule::from_str(&"*/5 * * * * *".to_string()).unwrap();
+        let next_ts1 = schedule1.next_after(&current_block_ts).unwrap();
+        println!("TS 1: {} {}", next_ts1, human_readable_time(next_ts1));
+        let denom1 = 5 * NANO;
+        let rem1 = start_ts.clone() % denom1;
+        assert_eq!(next_ts1, (start_ts.clone() - rem1) + denom1);
+
+        // Minutes
+        let schedule2 = Schedule::from_str(&"* */5 * * * *".to_string()).unwrap();
+        let next_ts2 = schedule2.next_after(&current_block_ts).unwrap();
+        println!("TS 2: {} {}", next_ts2, human_readable_time(next_ts2));
+        let denom2 = 5 * 60 * NANO;
+        let rem2 = start_ts.clone() % denom2;
+        assert_eq!(next_ts2, (start_ts.clone() - rem2) + denom2);
+
+        // Hours
+        let schedule3 = Schedule::from_str(&"* * */5 * * *".to_string()).unwrap();
+        let next_ts3 = schedule3.next_after(&current_block_ts).unwrap();
+        println!("TS 3: {} {}", next_ts3, human_readable_time(next_ts3));
+        assert_eq!(next_ts3, 1624165200000000000);
+
+        // Days
+        let schedule4 = Schedule::from_str(&"* * * 10 * *".to_string()).unwrap();
+        let next_ts4 = schedule4.next_after(&current_block_ts).unwrap();
+        println!("TS 4: {} {}", next_ts4, human_readable_time(next_ts4));
+        assert_eq!(next_ts4, 1625875200000000000);
 
         // Month
         let schedule5 = Schedule::from_str(&"* * * * 10 *".to_string()).unwrap();
@@ -875,42 +3036,740 @@ mod tests {
         println!("TS 5: {} {}", next_ts5, human_readable_time(next_ts5));
         assert_eq!(next_ts5, 1633046400000000000);
 
-        // Year
-        let schedule6 = Schedule::from_str(&"* * * * * * 2025".to_string()).unwrap();
-        let next_ts6 = schedule6.next_after(&current_block_ts).unwrap();
-        println!("TS 6: {} {}", next_ts6, human_readable_time(next_ts6));
-        assert_eq!(next_ts6, 1750381904000000000);
+        // Year
+        let schedule6 = Schedule::from_str(&"* * * * * * 2025".to_string()).unwrap();
+        let next_ts6 = schedule6.next_after(&current_block_ts).unwrap();
+        println!("TS 6: {} {}", next_ts6, human_readable_time(next_ts6));
+        assert_eq!(next_ts6, 1750381904000000000);
+    }
+
+    #[test]
+    fn test_get_slot_from_cadence_match() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let contract = Contract::new();
+        testing_env!(context
+            .is_view(false)
+            .block_index(BLOCK_START_BLOCK.clone() + 1)
+            .block_timestamp(BLOCK_START_TS.clone() + 1_000_000_000)
+            .build());
+        testing_env!(context.is_view(true).build());
+        let slot1 = get_slot_from_cadence(&contract, "*/5 * * * * *"); // Immediately next slot (since every 5 seconds)
+        println!("SLOT 1 {}", slot1);
+        assert_eq!(slot1, 52201080);
+        let slot2 = get_slot_from_cadence(&contract, "* */5 * * * *"); // Every 5 mins
+        println!("SLOT 2 {}", slot2);
+        assert_eq!(slot2, 52201200);
+        let slot3 = get_slot_from_cadence(&contract, "* * */5 * * *"); // Every 5th hour
+        println!("SLOT 3 {}", slot3);
+        assert_eq!(slot3, 52214700);
+        let slot4 = get_slot_from_cadence(&contract, "* * * 10 * *"); // The 10th day of Month
+        println!("SLOT 4 {}", slot4);
+        assert_eq!(slot4, 53924700);
+        let slot5 = get_slot_from_cadence(&contract, "* * * * 10 *"); // The 10th Month of the Year
+        println!("SLOT 5 {}", slot5);
+        assert_eq!(slot5, 61095900);
+        let slot6 = get_slot_from_cadence(&contract, "* * * * * * 2025");
+        println!("SLOT 6 {}", slot6);
+        assert_eq!(slot6, 178431420);
+    }
+
+    #[test]
+    fn test_task_proxy_highest_tip() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let low_tip = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        let high_tip = contract.create_task(
+            accounts(3),
+            Some("decrement".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                tip: Some(U128::from(100)),
+                ..Default::default()
+            }),
+        );
+        let low_tip_last = contract.create_task(
+            accounts(3),
+            Some("reset".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+        assert!(contract.tasks.get(&high_tip).is_none(), "Tipped task ran first");
+        assert_eq!(contract.slot_hashes(52201080), vec![low_tip, low_tip_last]);
+        assert_eq!(
+            contract.get_agent(accounts(1).to_string()).unwrap().balance.0,
+            2090000000000000000000 + AGENT_BASE_FEE + 200 * GAS_BASE_PRICE + 100
+        );
+    }
+
+    #[test]
+    fn test_task_slot_ordering() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let first = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (7 * NANO))
+            .block_index(BLOCK_START_BLOCK + 7)
+            .build());
+        let second = contract.create_task(
+            accounts(3),
+            Some("decrement".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                tip: Some(U128::from(100)),
+                ..Default::default()
+            }),
+        );
+        let third = contract.create_task(
+            accounts(3),
+            Some("reset".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        let (hashes, slot) = contract.get_tasks(None);
+        assert_eq!(slot.0, 52201080);
+        assert_eq!(contract.get_slot_ordering(), SlotOrdering::Priority);
+        assert_eq!(hashes, vec![second.clone(), first.clone(), third.clone()]);
+
+        // Order keys are fixed once slotted, changing the ordering re-slots every task
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (7 * NANO))
+            .block_index(BLOCK_START_BLOCK + 7)
+            .build());
+        contract.apply_settings(SettingsUpdate {
+            slot_ordering: Some(SlotOrdering::Fifo),
+            ..Default::default()
+        });
+        assert_eq!(contract.reslot_cursor, Some(0));
+        contract.reslot_tasks(None);
+        let (hashes, _) = contract.get_tasks(Some(60));
+        assert_eq!(contract.get_slot_ordering(), SlotOrdering::Fifo);
+        assert_eq!(hashes, vec![first.clone(), second.clone(), third.clone()]);
+
+        // Oldest task first, even when slotted later
+        contract.slot_ordering = SlotOrdering::CreatedAt;
+        for task_hash in &[&third, &second, &first] {
+            contract.reslot_task(task_hash);
+        }
+        let (hashes, _) = contract.get_tasks(Some(60));
+        assert_eq!(hashes, vec![first, third, second]);
+    }
+
+    #[test]
+    fn test_task_slot_capacity_overflow() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.slot_capacity = 2;
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let mut hashes = vec![];
+        for function_id in &["increment", "decrement", "reset"] {
+            hashes.push(contract.create_task(
+                accounts(3),
+                Some(function_id.to_string()),
+                "*/10 * * * * *".to_string(),
+                Some(false),
+                Some(U128::from(0)),
+                Some(200),
+                None,
+                None,
+            ));
+        }
+
+        assert_eq!(contract.slot_hashes(52201080).len(), 2);
+        assert_eq!(contract.slot_hashes(52201140), vec![hashes[2].clone()]);
+        assert_eq!(contract.get_task(hashes[2].clone()).next_slot.0, 52201140);
+        assert_eq!(
+            contract.get_slot_usage(None, None),
+            (vec![(U128::from(52201080), 2), (U128::from(52201140), 1)], 2)
+        );
+
+        // Removing a task frees up its slot
+        contract.remove_task(hashes[2].clone());
+        assert!(contract.slots.get(&52201140).is_none());
+    }
+
+    #[test]
+    fn test_slot_queue_lanes() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let mut hashes = vec![];
+        for function_id in &["increment", "decrement", "reset", "double"] {
+            hashes.push(contract.create_task(
+                accounts(3),
+                Some(function_id.to_string()),
+                "*/10 * * * * *".to_string(),
+                Some(false),
+                Some(U128::from(0)),
+                Some(200),
+                None,
+                None,
+            ));
+        }
+
+        // Equal order keys share a single lane
+        let queue = contract.slots.get(&52201080).unwrap();
+        assert_eq!(queue.lanes.len(), 1);
+        assert_eq!(queue.len(), 4);
+
+        // Removed entries are skipped over, the slot keeps its order
+        contract.remove_task(hashes[0].clone());
+        contract.remove_task(hashes[2].clone());
+        assert_eq!(contract.slot_hashes(52201080), vec![hashes[1].clone(), hashes[3].clone()]);
+        assert_eq!(contract.slot_pop(52201080), Some(hashes[1].clone()));
+        assert_eq!(contract.slot_pop(52201080), Some(hashes[3].clone()));
+        assert!(contract.slots.get(&52201080).is_none());
+        assert_eq!(contract.slot_pop(52201080), None);
     }
 
     #[test]
-    fn test_get_slot_from_cadence_match() {
+    fn test_task_jitter() {
         let mut context = get_context(accounts(1));
         testing_env!(context.build());
-        let contract = Contract::new();
+        let mut contract = Contract::new();
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let base = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */10 * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        let base_slot = contract.get_task(base).next_slot.0;
+
+        // Spread within the window, in whole slots
+        let jittered = contract.create_task(
+            accounts(3),
+            Some("decrement".to_string()),
+            "0 */10 * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                jitter: Some(300),
+                ..Default::default()
+            }),
+        );
+        // The task hash picks an offset of 4 out of the 6 slots in the window
+        let task = contract.get_task(jittered.clone());
+        assert_eq!(task.next_slot.0, base_slot + 4 * 60);
+        assert_eq!(contract.apply_jitter(&jittered.0, &task, base_slot), task.next_slot.0);
+
+        // Cadence shorter than a slot leaves no room for jitter
+        let short = Task {
+            cadence: "*/10 * * * * *".to_string(),
+            jitter: Some(300),
+            ..task
+        };
+        assert_eq!(contract.apply_jitter(&jittered.0, &short, base_slot), base_slot);
+    }
+
+    /// Runs a recurring task, every minute, 5 minutes after its slot
+    fn proxy_call_overdue(catch_up: CatchUpPolicy) -> (Contract, TaskHash) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        contract.register_agent(None);
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                catch_up: Some(catch_up),
+                ..Default::default()
+            }),
+        );
+        assert_eq!(contract.get_task(task_hash.clone()).next_slot.0, 52201080);
+
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (366 * NANO))
+            .block_index(BLOCK_START_BLOCK + 366)
+            .build());
+        contract.proxy_call();
+        (contract, task_hash)
+    }
+
+    #[test]
+    fn test_task_catch_up_once() {
+        let (contract, task_hash) = proxy_call_overdue(CatchUpPolicy::Once);
+        let task = contract.get_task(task_hash);
+        assert_eq!(task.skipped_runs, 4);
+        assert_eq!(task.catch_up_remaining, 0);
+        assert_eq!(task.next_ts.0, 1624151820 * NANO);
+        assert_eq!(
+            contract.get_agent(accounts(1).to_string()).unwrap().total_tasks_executed.0,
+            1
+        );
+    }
+
+    #[test]
+    fn test_task_catch_up_skip() {
+        let (contract, task_hash) = proxy_call_overdue(CatchUpPolicy::Skip);
+        let task = contract.get_task(task_hash);
+        assert_eq!(task.skipped_runs, 5);
+        assert_eq!(task.next_ts.0, 1624151880 * NANO);
+        assert!(task.next_slot.0 > 52201380, "Rescheduled in the future");
+        let agent = contract.get_agent(accounts(1).to_string()).unwrap();
+        assert_eq!(agent.total_tasks_executed.0, 0);
+        let agent_storage = Balance::from(contract.agent_storage_usage) * env::storage_byte_cost();
+        assert_eq!(agent.balance.0, agent_storage + AGENT_BASE_FEE, "Agent is paid the base fee");
+    }
+
+    #[test]
+    fn test_task_catch_up_skip_adjusted_fee() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            agent_fee_max: Some(U128::from(11 * AGENT_BASE_FEE)),
+            agent_fee_backlog: Some(10),
+            ..Default::default()
+        });
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        contract.register_agent(None);
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            Some(TaskOptions {
+                catch_up: Some(CatchUpPolicy::Skip),
+                ..Default::default()
+            }),
+        );
+
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (366 * NANO))
+            .block_index(BLOCK_START_BLOCK + 366)
+            .build());
+        let agent_fee = contract.get_agent_fee().0;
+        assert!(agent_fee > AGENT_BASE_FEE, "Fee adjusted to the backlog");
+        contract.proxy_call();
+
+        // The skipped call is paid the same effective fee as an executed one
+        let agent = contract.get_agent(accounts(1).to_string()).unwrap();
+        assert_eq!(agent.total_tasks_executed.0, 0);
+        let agent_storage = Balance::from(contract.agent_storage_usage) * env::storage_byte_cost();
+        assert_eq!(agent.balance.0, agent_storage + agent_fee);
+    }
+
+    #[test]
+    fn test_task_catch_up_all() {
+        let (mut contract, task_hash) = proxy_call_overdue(CatchUpPolicy::All(2));
+        let task = contract.get_task(task_hash.clone());
+        assert_eq!(task.skipped_runs, 2);
+        assert_eq!(task.catch_up_remaining, 2);
+        assert_eq!(task.next_ts.0, 1624151700 * NANO);
+
+        // Missed runs go in the following slots
+        let mut context = get_context(accounts(0));
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (367 * NANO))
+            .block_index(BLOCK_START_BLOCK + 367)
+            .build());
+        contract.callback_for_proxy_call(task_hash.clone(), U128::from(52201380));
+        let task = contract.get_task(task_hash);
+        assert_eq!(task.next_slot.0, 52201440);
+        assert_eq!(task.catch_up_remaining, 1);
+        assert_eq!(task.next_ts.0, 1624151760 * NANO);
+    }
+
+    #[test]
+    fn test_task_reschedule_from_next_ts() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        contract.register_agent(None);
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        assert_eq!(contract.get_task(task_hash.clone()).next_ts.0, 1624151520 * NANO);
+
+        // Executed 43 seconds late
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+
+        // Following run is one occurrence after the intended one
+        let mut context = get_context(accounts(0));
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (75 * NANO))
+            .block_index(BLOCK_START_BLOCK + 75)
+            .build());
+        contract.callback_for_proxy_call(task_hash.clone(), U128::from(52201080));
+        let task = contract.get_task(task_hash);
+        assert_eq!(task.next_ts.0, 1624151580 * NANO);
+        assert_eq!(task.next_slot.0, 52201140);
+        assert_eq!(task.skipped_runs, 0);
+    }
+
+    #[test]
+    fn test_task_reslot_timestamp_mode() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        contract.register_agent(None);
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        assert_eq!(contract.get_task(task_hash.clone()).next_slot.0, 52201080);
+
+        testing_env!(context.attached_deposit(0).build());
+        contract.apply_settings(SettingsUpdate {
+            slot_mode: Some(SlotMode::Timestamp),
+            ..Default::default()
+        });
+        assert_eq!(contract.reslot_cursor, Some(0));
+        contract.reslot_tasks(None);
+        assert_eq!(contract.reslot_cursor, None);
+
+        // Slots are now keyed by the minute the task fires
+        let task = contract.get_task(task_hash.clone());
+        assert_eq!(task.next_slot.0, 1624151520 * NANO as u128);
+        assert!(contract.slots.get(&52201080).is_none());
+
+        testing_env!(context
+            .block_timestamp(BLOCK_START_TS + (20 * NANO))
+            .block_index(BLOCK_START_BLOCK + 20)
+            .build());
+        let (hashes, slot) = contract.get_tasks(None);
+        assert_eq!(hashes, vec![task_hash]);
+        assert_eq!(slot.0, 1624151520 * NANO as u128);
+        contract.proxy_call();
+        assert_eq!(
+            contract.get_agent(accounts(1).to_string()).unwrap().total_tasks_executed.0,
+            1
+        );
+    }
+
+    #[test]
+    fn test_task_slot_mode_flip_in_flight() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            slot_mode: Some(SlotMode::Timestamp),
+            ..Default::default()
+        });
+        contract.reslot_tasks(None);
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        contract.register_agent(None);
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(true),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (20 * NANO))
+            .block_index(BLOCK_START_BLOCK + 20)
+            .build());
+        let current_slot = contract.get_slot_id(None);
+        contract.proxy_call();
+
+        // Back to block slots while the call is in flight
+        contract.apply_settings(SettingsUpdate {
+            slot_mode: Some(SlotMode::Block),
+            ..Default::default()
+        });
+        contract.reslot_tasks(None);
+
+        // The callback reschedules in block slots instead of panicking on the timestamp slot
+        testing_env!(get_context(accounts(0))
+            .block_timestamp(BLOCK_START_TS + (22 * NANO))
+            .block_index(BLOCK_START_BLOCK + 22)
+            .build());
+        contract.callback_for_proxy_call(task_hash.clone(), U128::from(current_slot));
+        let task = contract.get_task(task_hash);
+        assert!(task.next_slot.0 > u128::from(BLOCK_START_BLOCK + 22));
+        assert!(task.next_slot.0 < current_slot, "Slotted by block height");
+        assert!(contract.slots.get(&task.next_slot.0).is_some());
+    }
+
+    #[test]
+    fn test_task_reslot_granularity() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        assert_eq!(contract.get_task(task_hash.clone()).next_slot.0, 52201080);
+
+        testing_env!(context.attached_deposit(0).build());
+        contract.apply_settings(SettingsUpdate {
+            slot_granularity: Some(100),
+            ..Default::default()
+        });
+        assert_eq!(contract.reslot_cursor, Some(0));
+
+        // Paginated, one task at a time
+        contract.reslot_tasks(Some(1));
+        assert_eq!(contract.reslot_cursor, Some(1));
+        contract.reslot_tasks(Some(1));
+        assert_eq!(contract.reslot_cursor, None);
+
+        assert_eq!(contract.get_task(task_hash.clone()).next_slot.0, 52201100);
+        assert_eq!(contract.slot_hashes(52201100), vec![task_hash]);
+        assert!(contract.slots.get(&52201080).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Re-slotting tasks, execution paused")]
+    fn test_task_proxy_while_reslotting() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        let mut contract = Contract::new();
+        contract.register_agent(None);
+        contract.reslot_cursor = Some(0);
+        contract.proxy_call();
+    }
+
+    #[test]
+    #[should_panic(expected = "Re-slotting tasks, catalog changes paused")]
+    fn test_task_create_while_reslotting() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.reslot_cursor = Some(0);
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Re-slotting tasks, catalog changes paused")]
+    fn test_task_remove_while_reslotting() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "0 */1 * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        contract.reslot_cursor = Some(0);
+        contract.remove_task(task_hash);
+    }
+
+    #[test]
+    fn test_effective_agent_fee() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            agent_fee_max: Some(U128::from(11 * AGENT_BASE_FEE)),
+            agent_fee_backlog: Some(10),
+            ..Default::default()
+        });
+        assert_eq!(contract.get_agent_fee().0, AGENT_BASE_FEE, "No backlog");
+
         testing_env!(context
             .is_view(false)
-            .block_index(BLOCK_START_BLOCK.clone() + 1)
-            .block_timestamp(BLOCK_START_TS.clone() + 1_000_000_000)
+            .attached_deposit(1000000000020000000100 + STORAGE_DEPOSIT)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
             .build());
-        testing_env!(context.is_view(true).build());
-        let slot1 = contract.get_slot_from_cadence("*/5 * * * * *".to_string()); // Immediately next slot (since every 5 seconds)
-        println!("SLOT 1 {}", slot1);
-        assert_eq!(slot1, 52201080);
-        let slot2 = contract.get_slot_from_cadence("* */5 * * * *".to_string()); // Every 5 mins
-        println!("SLOT 2 {}", slot2);
-        assert_eq!(slot2, 52201200);
-        let slot3 = contract.get_slot_from_cadence("* * */5 * * *".to_string()); // Every 5th hour
-        println!("SLOT 3 {}", slot3);
-        assert_eq!(slot3, 52214700);
-        let slot4 = contract.get_slot_from_cadence("* * * 10 * *".to_string()); // The 10th day of Month
-        println!("SLOT 4 {}", slot4);
-        assert_eq!(slot4, 53924700);
-        let slot5 = contract.get_slot_from_cadence("* * * * 10 *".to_string()); // The 10th Month of the Year
-        println!("SLOT 5 {}", slot5);
-        assert_eq!(slot5, 61095900);
-        let slot6 = contract.get_slot_from_cadence("* * * * * * 2025".to_string());
-        println!("SLOT 6 {}", slot6);
-        assert_eq!(slot6, 178431420);
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+
+        // Task slot 52201080 is now 5 slots behind
+        testing_env!(context
+            .is_view(true)
+            .block_index(52201080 + 5 * SLOT_GRANULARITY)
+            .build());
+        assert_eq!(contract.get_agent_fee().0, 6 * AGENT_BASE_FEE);
+        assert_eq!(
+            contract.estimate_task_cost(None, Some(200), Some(2), None, None, None).0,
+            2 * (200 * GAS_BASE_PRICE + 6 * AGENT_BASE_FEE)
+        );
+
+        // Tip & the gas charged for results & the balance notification are included
+        let extra_gas = u128::from(GAS_FOR_CALLBACK + GAS_FOR_ON_RESULT + GAS_FOR_BALANCE_NOTIFY);
+        let tip = Some(U128::from(7));
+        assert_eq!(
+            contract.estimate_task_cost(None, Some(200), Some(2), tip, Some(true), Some(true)).0,
+            2 * ((200 + extra_gas) * GAS_BASE_PRICE + 6 * AGENT_BASE_FEE + 7)
+        );
+
+        // Capped at the max fee
+        testing_env!(context
+            .is_view(true)
+            .block_index(52201080 + 50 * SLOT_GRANULARITY)
+            .build());
+        assert_eq!(contract.get_agent_fee().0, 11 * AGENT_BASE_FEE);
     }
 
     #[test]
@@ -921,7 +3780,7 @@ mod tests {
         let task = get_sample_task();
         let hash = contract.hash(&task);
         assert_eq!(
-            hash,
+            hash.0,
             [32, 154, 253, 118, 34, 137, 134, 24, 119, 224, 187, 34, 173, 65, 86, 153, 220, 236, 185, 254, 202, 216, 153, 93, 113, 214, 29, 191, 129, 85, 146, 169],
             "Hash is not equivalent"
         )
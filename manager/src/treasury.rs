@@ -0,0 +1,378 @@
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreasuryInfo {
+    /// Account receiving all treasury withdrawals
+    pub account_id: Option<AccountId>,
+
+    /// Share of each execution fee credited to the treasury, in basis points
+    pub fee: u64,
+
+    /// Collected fees & yield, including the funds delegated to the staking pool
+    pub available_balance: U128,
+
+    /// Deposits paying for the manager's own tick task, kept apart from the collected fees
+    pub tick_balance: U128,
+
+    /// Staking pool receiving idle funds
+    pub staking_pool_id: Option<AccountId>,
+
+    /// Balance delegated to the staking pool, and unstaked balance awaiting withdrawal
+    pub staked_balance: U128,
+    pub unstaked_balance: U128,
+
+    /// Staking yield configuration & totals
+    pub yield_distribution: YieldDistribution,
+    pub total_yield: U128,
+    pub agent_reward_pool: U128,
+
+    /// Lifetime totals
+    pub total_collected: U128,
+    pub total_withdrawn: U128,
+
+    /// Withdrawal limits for the current period
+    pub withdraw_cap: U128,
+    pub withdraw_period: U64,
+    pub period_start: U64,
+    pub period_withdrawn: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Adds the attached deposit to the tick balance, funding the manager's own tick task.
+    /// Anyone can deposit. Deposits are kept apart from the collected fees, so treasury withdrawals can't take them.
+    ///
+    /// ```bash
+    /// near call cron.testnet deposit_treasury --accountId YOU.testnet --amount 10
+    /// ```
+    #[payable]
+    pub fn deposit_treasury(&mut self) -> U128 {
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "Must attach a deposit");
+        self.tick_balance += amount;
+        log_event(
+            "treasury_deposit",
+            json!({ "account_id": env::predecessor_account_id(), "amount": U128::from(amount) }),
+        );
+        U128::from(self.tick_balance)
+    }
+
+    /// Sends collected fees to the designated treasury account, the tick balance stays in the treasury.
+    /// Withdrawals are limited to `treasury_withdraw_cap` per `treasury_withdraw_period`,
+    /// and to the fees not delegated to the staking pool. A failed transfer is credited back.
+    /// Requires attaching 1 yoctoⓃ ensure it comes from a full-access key.
+    ///
+    /// ```bash
+    /// near call cron.testnet withdraw_treasury '{"amount": "1000000000000000000000000"}' --accountId cron.testnet --depositYocto 1
+    /// ```
+    #[payable]
+    pub fn withdraw_treasury(&mut self, amount: U128) -> Promise {
+        assert_one_yocto();
+        assert_eq!(self.owner_id, env::predecessor_account_id(), "Must be owner");
+        let account_id = self
+            .treasury_account_id
+            .clone()
+            .expect("Treasury account not set");
+        let amount = amount.0;
+        // Funds delegated to the staking pool need to be unstaked first
+        let withdrawable = self.treasury_withdrawable_balance();
+        assert!(
+            amount <= withdrawable,
+            "Not enough treasury balance, available {}",
+            withdrawable
+        );
+
+        // Start a new withdrawal period once the previous one has elapsed
+        let now = env::block_timestamp();
+        if now >= self.treasury_period_start + self.treasury_withdraw_period {
+            self.treasury_period_start = now;
+            self.treasury_period_withdrawn = 0;
+        }
+        assert!(
+            self.treasury_period_withdrawn + amount <= self.treasury_withdraw_cap,
+            "Withdrawal exceeds treasury cap, remaining for this period {}",
+            self.treasury_withdraw_cap - self.treasury_period_withdrawn
+        );
+
+        self.available_balance -= amount;
+        self.treasury_period_withdrawn += amount;
+        self.treasury_total_withdrawn += amount;
+        log_event(
+            "treasury_withdraw",
+            json!({ "account_id": account_id, "amount": U128::from(amount) }),
+        );
+        Promise::new(account_id).transfer(amount).then(
+            Promise::new(env::current_account_id()).function_call(
+                b"callback_for_treasury_withdraw".to_vec(),
+                json!({ "amount": U128::from(amount) }).to_string().into_bytes(),
+                0,
+                GAS_FOR_TREASURY_CALLBACK,
+            ),
+        )
+    }
+
+    /// Logic executed on the completion of a treasury withdrawal
+    /// A failed transfer comes back to the manager, it is credited back to the treasury
+    #[private]
+    pub fn callback_for_treasury_withdraw(&mut self, amount: U128) {
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return;
+        }
+        self.available_balance += amount.0;
+        self.treasury_period_withdrawn = self.treasury_period_withdrawn.saturating_sub(amount.0);
+        self.treasury_total_withdrawn = self.treasury_total_withdrawn.saturating_sub(amount.0);
+        log_event("treasury_withdraw_failed", json!({ "amount": amount }));
+    }
+
+    /// Gets the treasury totals & withdrawal limits
+    ///
+    /// ```bash
+    /// near view cron.testnet get_treasury
+    /// ```
+    pub fn get_treasury(&self) -> TreasuryInfo {
+        TreasuryInfo {
+            account_id: self.treasury_account_id.clone(),
+            fee: self.treasury_fee,
+            available_balance: U128::from(self.available_balance),
+            tick_balance: U128::from(self.tick_balance),
+            staking_pool_id: self.staking_pool_id.clone(),
+            staked_balance: U128::from(self.staked_balance),
+            unstaked_balance: U128::from(self.unstaked_balance),
+            yield_distribution: self.yield_distribution,
+            total_yield: U128::from(self.total_yield),
+            agent_reward_pool: U128::from(self.agent_reward_pool),
+            total_collected: U128::from(self.treasury_total_collected),
+            total_withdrawn: U128::from(self.treasury_total_withdrawn),
+            withdraw_cap: U128::from(self.treasury_withdraw_cap),
+            withdraw_period: U64::from(self.treasury_withdraw_period),
+            period_start: U64::from(self.treasury_period_start),
+            period_withdrawn: U128::from(self.treasury_period_withdrawn),
+        }
+    }
+}
+
+// Internal methods
+impl Contract {
+    /// Collected fees that can be withdrawn
+    /// Funds delegated to the staking pool are taken from the fees first, keeping the tick balance liquid
+    pub(crate) fn treasury_withdrawable_balance(&self) -> Balance {
+        self.available_balance
+            .saturating_sub(self.staked_balance + self.unstaked_balance)
+    }
+
+    /// Pays for an execution of the tick task from the tick balance, then from the collected fees
+    /// Returns false, paying nothing, when the liquid treasury funds don't cover it
+    pub(crate) fn pay_tick(&mut self, amount: Balance) -> bool {
+        let from_tick = core::cmp::min(amount, self.tick_balance);
+        let from_fees = amount - from_tick;
+        if amount > self.treasury_liquid_balance() || from_fees > self.treasury_withdrawable_balance() {
+            return false;
+        }
+        self.tick_balance -= from_tick;
+        self.available_balance -= from_fees;
+        true
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::test_utils::{accounts, testing_env_with_promise_results, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    const BLOCK_START_BLOCK: u64 = 52_201_040;
+    const BLOCK_START_TS: u64 = 1_624_151_503_447_000_000;
+
+    fn get_context(predecessor_account_id: ValidAccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .signer_account_pk(b"ed25519:4ZhGmuKTfQn9ZpHCQVRwEr4JnutL8Uu3kArfxEqksfVM".to_vec())
+            .predecessor_account_id(predecessor_account_id)
+            .block_index(BLOCK_START_BLOCK)
+            .block_timestamp(BLOCK_START_TS);
+        builder
+    }
+
+    #[test]
+    fn test_treasury_fee_share() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            treasury_fee: Some(1_000),
+            ..Default::default()
+        });
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        contract.register_agent(None);
+
+        testing_env!(context
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(BLOCK_START_TS + (6 * NANO))
+            .block_index(BLOCK_START_BLOCK + 6)
+            .build());
+        contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+
+        testing_env!(context
+            .attached_deposit(0)
+            .block_timestamp(BLOCK_START_TS + (60 * NANO))
+            .block_index(BLOCK_START_BLOCK + 60)
+            .build());
+        contract.proxy_call();
+
+        // 10% of the agent fee + gas fee
+        testing_env!(context.is_view(true).build());
+        let treasury = contract.get_treasury();
+        assert_eq!(treasury.available_balance.0, 100_000_000_002_000_000_000);
+        assert_eq!(treasury.total_collected.0, 100_000_000_002_000_000_000);
+        assert_eq!(
+            contract.get_agent(accounts(1).to_string()).unwrap().balance.0,
+            2090000000000000000000 + 900_000_000_018_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_treasury_deposit() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        assert_eq!(contract.deposit_treasury().0, ONE_NEAR);
+        contract.deposit_treasury();
+        testing_env!(context.is_view(true).build());
+        let treasury = contract.get_treasury();
+        assert_eq!(treasury.tick_balance.0, 2 * ONE_NEAR);
+        assert_eq!(treasury.available_balance.0, 0);
+        assert_eq!(treasury.total_collected.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Must attach a deposit")]
+    fn test_treasury_deposit_empty() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.deposit_treasury();
+    }
+
+    #[test]
+    fn test_treasury_withdraw() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            treasury_account_id: Some(accounts(2)),
+            treasury_withdraw_cap: Some(U128::from(ONE_NEAR)),
+            ..Default::default()
+        });
+        contract.available_balance = 5 * ONE_NEAR;
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw_treasury(U128::from(ONE_NEAR));
+        testing_env!(context.is_view(true).build());
+        let treasury = contract.get_treasury();
+        assert_eq!(treasury.available_balance.0, 4 * ONE_NEAR);
+        assert_eq!(treasury.total_withdrawn.0, ONE_NEAR);
+        assert_eq!(treasury.period_withdrawn.0, ONE_NEAR);
+
+        // Cap is reset once the period has elapsed
+        testing_env!(context
+            .is_view(false)
+            .block_timestamp(BLOCK_START_TS + TREASURY_WITHDRAW_PERIOD)
+            .build());
+        contract.withdraw_treasury(U128::from(ONE_NEAR));
+        testing_env!(context.is_view(true).build());
+        assert_eq!(contract.get_treasury().total_withdrawn.0, 2 * ONE_NEAR);
+    }
+
+    #[test]
+    fn test_treasury_withdraw_failed() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            treasury_account_id: Some(accounts(2)),
+            ..Default::default()
+        });
+        contract.available_balance = 5 * ONE_NEAR;
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw_treasury(U128::from(ONE_NEAR));
+
+        // The failed transfer is credited back, it doesn't count towards the cap
+        testing_env_with_promise_results(context.attached_deposit(0).build(), PromiseResult::Failed);
+        contract.callback_for_treasury_withdraw(U128::from(ONE_NEAR));
+        testing_env!(context.is_view(true).build());
+        let treasury = contract.get_treasury();
+        assert_eq!(treasury.available_balance.0, 5 * ONE_NEAR);
+        assert_eq!(treasury.total_withdrawn.0, 0);
+        assert_eq!(treasury.period_withdrawn.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough treasury balance")]
+    fn test_treasury_withdraw_tick_balance() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            treasury_account_id: Some(accounts(2)),
+            ..Default::default()
+        });
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.deposit_treasury();
+
+        // Tick deposits can't be withdrawn
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw_treasury(U128::from(1));
+    }
+
+    #[test]
+    fn test_pay_tick() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.tick_balance = 2 * ONE_NEAR;
+        contract.available_balance = 2 * ONE_NEAR;
+
+        // The tick balance pays first, the fees cover the rest
+        assert!(contract.pay_tick(3 * ONE_NEAR));
+        assert_eq!(contract.tick_balance, 0);
+        assert_eq!(contract.available_balance, ONE_NEAR);
+
+        // Delegated fees can't pay
+        contract.staked_balance = ONE_NEAR;
+        assert!(!contract.pay_tick(1));
+        assert_eq!(contract.available_balance, ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawal exceeds treasury cap")]
+    fn test_treasury_withdraw_over_cap() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.apply_settings(SettingsUpdate {
+            treasury_account_id: Some(accounts(2)),
+            treasury_withdraw_cap: Some(U128::from(ONE_NEAR)),
+            ..Default::default()
+        });
+        contract.available_balance = 5 * ONE_NEAR;
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw_treasury(U128::from(ONE_NEAR));
+        contract.withdraw_treasury(U128::from(1));
+    }
+}
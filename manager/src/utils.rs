@@ -1,10 +1,44 @@
 use crate::*;
 
+/// Contract state before the upgrade, as deployed by the initial release
+#[derive(BorshDeserialize, BorshSerialize)]
+struct OldContract {
+    paused: bool,
+    owner_id: AccountId,
+    bps_block: [u64; 2],
+    bps_timestamp: [u64; 2],
+    agents: LookupMap<AccountId, Agent>,
+    slots: TreeMap<u128, Vec<Vec<u8>>>,
+    tasks: UnorderedMap<Vec<u8>, OldTask>,
+    available_balance: Balance,
+    staked_balance: Balance,
+    agent_fee: Balance,
+    gas_price: Balance,
+    proxy_callback_gas: Gas,
+    slot_granularity: u64,
+    agent_storage_usage: StorageUsage,
+}
+
+/// Task as stored by the initial release
+#[derive(BorshDeserialize, BorshSerialize)]
+struct OldTask {
+    owner_id: AccountId,
+    contract_id: AccountId,
+    function_id: String,
+    cadence: String,
+    recurring: bool,
+    total_deposit: U128,
+    deposit: U128,
+    gas: Gas,
+    arguments: Base64VecU8,
+}
+
 #[near_bindgen]
 impl Contract {
     // NOTE: For large state transitions, needs to be able to migrate over paginated sets?
     /// Migrate State
     /// Safely upgrade contract storage
+    /// Tasks keep their hash, stored as a fixed size hash now, & are slotted again using the current slot settings
     ///
     /// ```bash
     /// near call cron.testnet migrate_state --accountId cron.testnet
@@ -13,7 +47,7 @@ impl Contract {
     #[private]
     pub fn migrate_state() -> Self {
         // Deserialize the state using the old contract structure.
-        let old_contract: Contract = env::state_read().expect("Old state doesn't exist");
+        let mut old_contract: OldContract = env::state_read().expect("Old state doesn't exist");
         // Verify that the migration can only be done by the owner.
         // This is not necessary, if the upgrade is done internally.
         assert_eq!(
@@ -22,50 +56,149 @@ impl Contract {
             "Can only be called by the owner"
         );
 
+        // Old tasks & slots are keyed differently, take them out before the new collections reuse the prefixes
+        let old_tasks: Vec<(Vec<u8>, OldTask)> = old_contract.tasks.iter().collect();
+        old_contract.tasks.clear();
+        old_contract.slots.clear();
+
         // Create the new contract using the data from the old contract.
-        // Contract { owner_id: old_contract.owner_id, data: old_contract.data, new_data }
-        Contract {
-            paused: false,
-            owner_id: old_contract.owner_id,
-            bps_block: old_contract.bps_block,
-            bps_timestamp: old_contract.bps_timestamp,
-            tasks: old_contract.tasks,
-            agents: old_contract.agents,
-            slots: old_contract.slots,
-            available_balance: old_contract.available_balance,
-            staked_balance: old_contract.staked_balance,
-            agent_fee: old_contract.agent_fee,
-            gas_price: old_contract.gas_price,
-            proxy_callback_gas: old_contract.proxy_callback_gas,
-            slot_granularity: old_contract.slot_granularity,
-            agent_storage_usage: old_contract.agent_storage_usage,
+        let mut contract = Contract::new();
+        contract.paused = old_contract.paused;
+        contract.owner_id = old_contract.owner_id;
+        contract.bps_samples = vec![
+            (old_contract.bps_block[0], old_contract.bps_timestamp[0]),
+            (old_contract.bps_block[1], old_contract.bps_timestamp[1]),
+        ];
+        contract.bps_sample_index = 2;
+        contract.agents = old_contract.agents;
+        contract.available_balance = old_contract.available_balance;
+        contract.staked_balance = old_contract.staked_balance;
+        contract.agent_fee = old_contract.agent_fee;
+        contract.agent_fee_max = core::cmp::max(old_contract.agent_fee, contract.agent_fee_max);
+        contract.gas_price = old_contract.gas_price;
+        contract.proxy_callback_gas = old_contract.proxy_callback_gas;
+        contract.slot_granularity = old_contract.slot_granularity;
+        contract.agent_storage_usage = old_contract.agent_storage_usage;
+
+        for (old_hash, old_task) in old_tasks {
+            let task = Task {
+                owner_id: old_task.owner_id,
+                contract_id: old_task.contract_id,
+                function_id: old_task.function_id,
+                kind: ActionKind::FunctionCall,
+                cadence: old_task.cadence,
+                recurring: old_task.recurring,
+                total_deposit: old_task.total_deposit,
+                storage_deposit: U128::from(0),
+                storage_prepaid: false,
+                credit_cap: None,
+                credit_used: U128::from(0),
+                sponsor_id: None,
+                approved_sponsor_id: None,
+                owner_deposit: U128::from(0),
+                balance_notify: None,
+                on_result: None,
+                deposit: old_task.deposit,
+                gas: old_task.gas,
+                arguments: old_task.arguments,
+                actions: vec![],
+                tip: U128::from(0),
+                created_at: U64::from(env::block_timestamp()),
+                jitter: None,
+                catch_up: CatchUpPolicy::Once,
+                catch_up_remaining: 0,
+                skipped_runs: 0,
+                executions: 0,
+                next_ts: U64::from(0),
+                next_slot: U128::from(0),
+                yield_checkpoint: U128::from(contract.yield_per_deposit),
+                accrued_yield: U128::from(0),
+            };
+            let task_hash = contract.insert_task(task);
+            assert_eq!(task_hash.0.to_vec(), old_hash, "Migrated task hash changed");
         }
+        contract
     }
 
     /// Tick: Cron Manager Heartbeat
     /// Used to aid computation of blocks per second, manage internal use of funds
-    /// NOTE: This is a small array, allowing the adjustment of the previous block in the past
-    /// so the block tps average is always using more block distance than "now", ideally ~1000 blocks
+    /// NOTE: Samples go in a small ring buffer, so the block tps average always spans
+    /// several ticks worth of blocks, see `get_bps_estimate`
     ///
     /// near call cron.testnet tick '{}'
     pub fn tick(&mut self) {
-        let prev_block = self.bps_block[0];
-        let prev_timestamp = self.bps_timestamp[0];
-
-        // Check that we dont allow 0 BPS
-        assert!(prev_block + 10 < env::block_index(), "Tick triggered too soon");
-
-        self.bps_block[0] = env::block_index();
-        self.bps_block[1] = prev_block;
-        self.bps_timestamp[0] = env::block_timestamp();
-        self.bps_timestamp[1] = prev_timestamp;
+        // Check that we dont allow 0 BPS, nor staking actions on every call
+        if !self.record_bps_sample(BPS_TICK_INTERVAL) {
+            log!("Tick triggered too soon");
+            return;
+        }
 
-        // TBD: Internal staking management
         log!(
-            "Balances: Available {}, Staked {}",
+            "Balances: Available {}, Tick {}, Staked {}",
             self.available_balance,
+            self.tick_balance,
             self.staked_balance
         );
+
+        // Internal staking management
+        self.manage_staking();
+    }
+}
+
+// Internal methods
+impl Contract {
+    /// Replaces the manager's own recurring `tick` task, executed by agents like any other task
+    /// The task holds no balance, each execution is paid for by the treasury
+    /// NOTE: A tick task created by hand with the same cadence is adopted as is
+    pub(crate) fn schedule_tick_task(&mut self, cadence: String) {
+        let task = Task {
+            owner_id: env::current_account_id(),
+            contract_id: env::current_account_id(),
+            function_id: "tick".to_string(),
+            kind: ActionKind::FunctionCall,
+            cadence,
+            recurring: true,
+            total_deposit: U128::from(0),
+            storage_deposit: U128::from(0),
+            storage_prepaid: false,
+            credit_cap: None,
+            credit_used: U128::from(0),
+            sponsor_id: None,
+            approved_sponsor_id: None,
+            owner_deposit: U128::from(0),
+            balance_notify: None,
+            on_result: None,
+            deposit: U128::from(0),
+            gas: GAS_FOR_TICK,
+            arguments: Base64VecU8::from(vec![]),
+            actions: vec![],
+            tip: U128::from(0),
+            created_at: U64::from(env::block_timestamp()),
+            jitter: Some(0),
+            catch_up: CatchUpPolicy::Once,
+            catch_up_remaining: 0,
+            skipped_runs: 0,
+            executions: 0,
+            next_ts: U64::from(0),
+            next_slot: U128::from(0),
+            yield_checkpoint: U128::from(self.yield_per_deposit),
+            accrued_yield: U128::from(0),
+        };
+        let task_hash = self.hash(&task);
+        if task_hash == self.tick_task_hash {
+            return;
+        }
+
+        if self.tasks.get(&self.tick_task_hash).is_some() {
+            self.exit_task(self.tick_task_hash.clone());
+        }
+        let cadence = task.cadence.clone();
+        self.tick_task_hash = if self.tasks.get(&task_hash).is_some() {
+            task_hash
+        } else {
+            self.insert_task(task)
+        };
+        log_event("tick_schedule", json!({ "cadence": cadence }));
     }
 }
 
@@ -73,7 +206,7 @@ impl Contract {
 mod tests {
     use super::*;
     use near_sdk::json_types::ValidAccountId;
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
     use near_sdk::{testing_env, MockedBlockchain};
 
     const BLOCK_START_BLOCK: u64 = 52_201_040;
@@ -97,14 +230,231 @@ mod tests {
         testing_env!(context.is_view(false).build());
         let mut contract = Contract::new();
         testing_env!(context.is_view(true).build());
-        assert_eq!(contract.bps_block[0], 52201040);
+        assert_eq!(contract.bps_samples[0].0, 52201040);
         testing_env!(context.is_view(false).block_index(52201240).build());
         contract.tick();
         testing_env!(context.is_view(false).block_index(52207040).build());
         contract.tick();
         testing_env!(context.is_view(false).block_index(52208540).build());
         testing_env!(context.is_view(true).build());
-        assert_eq!(contract.bps_block[0], 52207040);
-        assert_eq!(contract.bps_block[1], 52201240);
+        let samples: Vec<u64> = contract.bps_samples.iter().map(|(block, _)| *block).collect();
+        assert_eq!(samples, vec![52201040, 52201240, 52207040]);
+    }
+
+    #[test]
+    fn test_tick_too_soon() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.is_view(false).build());
+        let mut contract = Contract::new();
+        testing_env!(context.is_view(false).block_index(52207040).build());
+        contract.tick();
+        testing_env!(context.is_view(false).block_index(52207045).build());
+        contract.tick();
+        assert_eq!(get_logs().last().unwrap(), "Tick triggered too soon");
+        testing_env!(context.is_view(true).build());
+        let samples: Vec<u64> = contract.bps_samples.iter().map(|(block, _)| *block).collect();
+        assert_eq!(samples, vec![52201040, 52207040]);
+    }
+
+    #[test]
+    fn test_migrate_state() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut old_tasks = UnorderedMap::new(StorageKeys::Tasks);
+        let mut old_slots = TreeMap::new(StorageKeys::Slots);
+        let old_hash = env::sha256(
+            format!(
+                "{:?}{:?}{:?}{:?}",
+                accounts(3).to_string(),
+                "increment".to_string(),
+                "0 */1 * * * *".to_string(),
+                accounts(1).to_string()
+            )
+            .as_bytes(),
+        );
+        old_tasks.insert(
+            &old_hash,
+            &OldTask {
+                owner_id: accounts(1).to_string(),
+                contract_id: accounts(3).to_string(),
+                function_id: "increment".to_string(),
+                cadence: "0 */1 * * * *".to_string(),
+                recurring: true,
+                total_deposit: U128::from(ONE_NEAR),
+                deposit: U128::from(0),
+                gas: 200,
+                arguments: Base64VecU8::from(vec![]),
+            },
+        );
+        old_slots.insert(&52201080, &vec![old_hash]);
+        env::state_write(&OldContract {
+            paused: false,
+            owner_id: accounts(0).to_string(),
+            bps_block: [BLOCK_START_BLOCK - 100, BLOCK_START_BLOCK],
+            bps_timestamp: [BLOCK_START_TS - 100 * NANO, BLOCK_START_TS],
+            agents: LookupMap::new(StorageKeys::Agents),
+            slots: old_slots,
+            tasks: old_tasks,
+            available_balance: ONE_NEAR,
+            staked_balance: 0,
+            agent_fee: 2 * AGENT_BASE_FEE,
+            gas_price: GAS_BASE_PRICE,
+            proxy_callback_gas: GAS_FOR_CALLBACK,
+            slot_granularity: SLOT_GRANULARITY,
+            agent_storage_usage: 100,
+        });
+
+        let contract = Contract::migrate_state();
+        assert_eq!(contract.owner_id, accounts(0).to_string());
+        assert_eq!(contract.agent_fee, 2 * AGENT_BASE_FEE);
+        assert_eq!(contract.available_balance, ONE_NEAR);
+        assert_eq!(contract.agent_storage_usage, 100);
+        assert_eq!(contract.total_task_deposits, ONE_NEAR);
+
+        // The task keeps its hash & is slotted again, next to the manager's own tick
+        let mut task_hash = [0u8; 32];
+        task_hash.copy_from_slice(&old_hash);
+        let task = contract.tasks.get(&TaskHash(task_hash)).unwrap();
+        assert_eq!(task.owner_id, accounts(1).to_string());
+        assert_eq!(task.next_slot.0, 52201080);
+        assert!(contract.get_tasks(Some(60)).0.contains(&TaskHash(task_hash)));
+        assert!(contract.tasks.get(&contract.tick_task_hash).is_some());
+    }
+
+    #[test]
+    fn test_tick_task() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let tick_task = contract.tasks.get(&contract.tick_task_hash).unwrap();
+        assert_eq!(tick_task.function_id, "tick");
+        assert_eq!(tick_task.cadence, TICK_CADENCE);
+        assert_eq!(tick_task.total_deposit.0, 0);
+
+        // Changing the cadence replaces the task
+        let prev_hash = contract.tick_task_hash.clone();
+        contract.apply_settings(SettingsUpdate {
+            tick_cadence: Some("0 */30 * * * *".to_string()),
+            ..Default::default()
+        });
+        assert!(contract.tasks.get(&prev_hash).is_none());
+        assert_eq!(contract.tasks.len(), 1);
+
+        // The tick task is left out of the task views
+        assert!(contract.get_all_tasks(None).is_empty());
+        assert!(contract.get_slot_usage(None, None).0.is_empty());
+        assert_eq!(
+            contract.tasks.get(&contract.tick_task_hash).unwrap().cadence,
+            "0 */30 * * * *"
+        );
+    }
+
+    #[test]
+    fn test_tick_task_paid_by_treasury() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        contract.register_agent(None);
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.deposit_treasury();
+        let tick_slot = contract.tasks.get(&contract.tick_task_hash).unwrap().next_slot.0;
+
+        testing_env!(context
+            .attached_deposit(0)
+            .block_index(tick_slot as u64 + 10)
+            .block_timestamp(BLOCK_START_TS + 3_600 * NANO)
+            .build());
+        contract.proxy_call();
+
+        // Agent is paid from the treasury, the task stays scheduled
+        let cost = u128::from(GAS_FOR_TICK) * contract.gas_price + contract.agent_fee;
+        assert_eq!(contract.tick_balance, ONE_NEAR - cost);
+        assert_eq!(
+            contract.get_agent(accounts(1).to_string()).unwrap().total_tasks_executed.0,
+            1
+        );
+        let tick_task = contract.tasks.get(&contract.tick_task_hash).unwrap();
+        assert_eq!(tick_task.total_deposit.0, 0);
+    }
+
+    #[test]
+    fn test_tick_task_unfunded() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.attached_deposit(2090000000000000000000).build());
+        contract.register_agent(None);
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        let task_hash = contract.create_task(
+            accounts(3),
+            Some("increment".to_string()),
+            "*/10 * * * * *".to_string(),
+            Some(false),
+            Some(U128::from(0)),
+            Some(200),
+            None,
+            None,
+        );
+        let tick_slot = contract.tasks.get(&contract.tick_task_hash).unwrap().next_slot.0;
+
+        testing_env!(context
+            .attached_deposit(0)
+            .block_index(tick_slot as u64 + 10)
+            .block_timestamp(BLOCK_START_TS + 3_600 * NANO)
+            .build());
+        contract.proxy_call();
+
+        // The tick is skipped without treasury funds, the agent continues with the next due task
+        let tick_task = contract.tasks.get(&contract.tick_task_hash).unwrap();
+        assert!(tick_task.next_slot.0 > tick_slot);
+        assert!(contract.tasks.get(&task_hash).is_none());
+        assert_eq!(contract.tick_balance, 0);
+        assert_eq!(
+            contract.get_agent(accounts(1).to_string()).unwrap().total_tasks_executed.0,
+            1
+        );
+    }
+
+    #[test]
+    fn test_bps_ring_buffer() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        for i in 1..=BPS_SAMPLE_SIZE + 2 {
+            testing_env!(context
+                .block_index(BLOCK_START_BLOCK + i * 100)
+                .block_timestamp(BLOCK_START_TS + i * 100 * NANO)
+                .build());
+            contract.tick();
+        }
+
+        // Oldest samples get overwritten
+        assert_eq!(contract.bps_samples.len() as u64, BPS_SAMPLE_SIZE);
+        assert_eq!(contract.bps_samples[0].0, BLOCK_START_BLOCK + 1200);
+        assert_eq!(contract.bps_samples[2].0, BLOCK_START_BLOCK + 1400);
+        assert_eq!(contract.get_bps(), 1_000);
+    }
+
+    #[test]
+    fn test_bps_outliers() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+
+        // 1 block per second, except a stalled chain & a burst of 5 blocks per second
+        contract.bps_samples = vec![
+            (BLOCK_START_BLOCK - 1_000, BLOCK_START_TS - 2_000 * NANO),
+            (BLOCK_START_BLOCK - 900, BLOCK_START_TS - 1_900 * NANO),
+            (BLOCK_START_BLOCK - 800, BLOCK_START_TS - 1_000 * NANO),
+            (BLOCK_START_BLOCK - 700, BLOCK_START_TS - 900 * NANO),
+            (BLOCK_START_BLOCK - 200, BLOCK_START_TS - 800 * NANO),
+            (BLOCK_START_BLOCK - 100, BLOCK_START_TS - 700 * NANO),
+        ];
+        testing_env!(context
+            .block_index(BLOCK_START_BLOCK)
+            .block_timestamp(BLOCK_START_TS - 600 * NANO)
+            .build());
+        assert_eq!(contract.get_bps(), 1_000);
     }
 }
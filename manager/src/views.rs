@@ -1,4 +1,5 @@
 use crate::*;
+use std::ops::Bound;
 
 #[near_bindgen]
 impl Contract {
@@ -12,49 +13,64 @@ impl Contract {
     }
 
     /// Gets a set of tasks.
-    /// Default: Returns the next executable set of tasks hashes.
+    /// Default: Returns the next executable set of tasks hashes, in the order they will be executed,
+    /// along with the slot. See `get_slot_ordering` for the policy that defines the order.
     ///
     /// Optional Parameters:
     /// "offset" - An unsigned integer specifying how far in the future to check for tasks that are slotted.
+    ///            In blocks, or seconds with `SlotMode::Timestamp`.
     ///
     /// ```bash
     /// near view cron.testnet get_tasks
     /// ```
-    pub fn get_tasks(&self, offset: Option<u64>) -> (Vec<Base64VecU8>, U128) {
+    pub fn get_tasks(&self, offset: Option<u64>) -> (Vec<TaskHash>, U128) {
+        let offset = match self.slot_mode {
+            SlotMode::Block => offset,
+            SlotMode::Timestamp => offset.map(|o| o * NANO),
+        };
         let current_slot = self.get_slot_id(offset);
 
         // Get tasks based on current slot.
         // (Or closest past slot if there are leftovers.)
         let slot_ballpark = self.slots.floor_key(&current_slot);
         if let Some(k) = slot_ballpark {
-            let ret: Vec<Base64VecU8> =
-                self.slots.get(&k).unwrap().into_iter().map(Base64VecU8::from).collect();
-
-            (ret, U128::from(current_slot))
+            (self.slot_hashes(k), U128::from(current_slot))
         } else {
             (vec![], U128::from(current_slot))
         }
     }
 
+    /// Gets the policy ordering the tasks within each slot, as returned by `get_tasks`
+    ///
+    /// ```bash
+    /// near view cron.testnet get_slot_ordering
+    /// ```
+    pub fn get_slot_ordering(&self) -> SlotOrdering {
+        self.slot_ordering
+    }
+
     /// Returns task data
-    /// Used by the frontend for viewing tasks
+    /// Used by the frontend for viewing tasks, the manager's own tick task is left out
     /// REF: https://docs.near.org/docs/concepts/data-storage#gas-consumption-examples-1
     // TODO: Add offset, limit for pagination
     pub fn get_all_tasks(&self, slot: Option<U128>) -> Vec<Task> {
         let mut ret: Vec<Task> = Vec::new();
         if let Some(U128(slot_number)) = slot {
             // User specified a slot number, only return tasks in there.
-            let tasks_in_slot = self
-                .slots
-                .get(&slot_number)
-                .unwrap_or_default();
+            let tasks_in_slot = self.slot_hashes(slot_number);
             for task_hash in tasks_in_slot.iter() {
+                if *task_hash == self.tick_task_hash {
+                    continue;
+                }
                 let task = self.tasks.get(&task_hash).expect("No task found by hash");
                 ret.push(task);
             }
         } else {
             // Return all tasks
-            for (_, task) in self.tasks.iter() {
+            for (task_hash, task) in self.tasks.iter() {
+                if task_hash == self.tick_task_hash {
+                    continue;
+                }
                 ret.push(task);
             }
         }
@@ -66,9 +82,146 @@ impl Contract {
     /// ```bash
     /// near view cron.testnet get_task '{"task_hash": "r2Jv…T4U4="}'
     /// ```
-    pub fn get_task(&self, task_hash: Base64VecU8) -> Task {
-        let task_hash = task_hash.0;
+    pub fn get_task(&self, task_hash: TaskHash) -> Task {
         let task = self.tasks.get(&task_hash).expect("No task found by hash");
         task
     }
-}
\ No newline at end of file
+
+    /// Gets the staking yield accrued by a single task so far
+    ///
+    /// ```bash
+    /// near view cron.testnet get_task_yield '{"task_hash": "r2Jv…T4U4="}'
+    /// ```
+    pub fn get_task_yield(&self, task_hash: TaskHash) -> U128 {
+        let mut task = self.tasks.get(&task_hash).expect("No task found by hash");
+        self.settle_task_yield(&mut task);
+        task.accrued_yield
+    }
+
+    /// Returns the agent fee currently paid per task execution, based on the slot backlog
+    ///
+    /// ```bash
+    /// near view cron.testnet get_agent_fee
+    /// ```
+    pub fn get_agent_fee(&self) -> U128 {
+        U128::from(self.get_effective_agent_fee())
+    }
+
+    /// Estimates the balance needed to fund a number of task executions at the current agent fee
+    /// NOTE: The agent fee can change with the slot backlog, consider funding extra executions
+    /// For tasks with following actions, pass the deposit & gas summed over all calls
+    ///
+    /// Optional Parameters:
+    /// "tip" - Tip paid to the agent each execution
+    /// "on_result" - Whether the task reports its results, charging the result callback gas
+    /// "balance_notify" - Whether the task notifies on a low balance, charging the notification gas
+    ///
+    /// ```bash
+    /// near view cron.testnet estimate_task_cost '{"deposit": "0", "gas": 2400000000000, "executions": 24, "tip": "0", "on_result": true}'
+    /// ```
+    pub fn estimate_task_cost(
+        &self,
+        deposit: Option<U128>,
+        gas: Option<Gas>,
+        executions: Option<u64>,
+        tip: Option<U128>,
+        on_result: Option<bool>,
+        balance_notify: Option<bool>,
+    ) -> U128 {
+        let gas = gas.unwrap_or(GAS_BASE_FEE)
+            + Task::extra_gas(on_result.unwrap_or(false), balance_notify.unwrap_or(false));
+        let per_execution = deposit.map(|v| v.0).unwrap_or(0)
+            + u128::from(gas) * self.gas_price
+            + self.get_effective_agent_fee()
+            + tip.map(|v| v.0).unwrap_or(0);
+        U128::from(per_execution * u128::from(executions.unwrap_or(1)))
+    }
+
+    /// Returns how many task hashes each slot holds, along with the per slot capacity (0 is unlimited)
+    /// Slots at capacity overflow into the following slots, the manager's own tick task isn't counted
+    ///
+    /// Optional Parameters:
+    /// "from_slot" - First slot to include, defaults to the earliest pending slot
+    /// "limit" - Max number of slots returned
+    ///
+    /// ```bash
+    /// near view cron.testnet get_slot_usage '{"limit": 10}'
+    /// ```
+    pub fn get_slot_usage(
+        &self,
+        from_slot: Option<U128>,
+        limit: Option<u64>,
+    ) -> (Vec<(U128, u64)>, u64) {
+        let start = match from_slot {
+            Some(U128(slot)) => Bound::Included(slot),
+            None => Bound::Unbounded,
+        };
+        // The tick task isn't slotted while its call is in flight
+        let tick_slot = self
+            .tasks
+            .get(&self.tick_task_hash)
+            .filter(|_| self.slot_keys.contains_key(&self.tick_task_hash))
+            .map(|task| task.next_slot.0);
+        let usage = self
+            .slots
+            .range((start, Bound::Unbounded))
+            .map(|(slot, queue)| {
+                let count = queue.len() - u64::from(tick_slot == Some(slot));
+                (U128::from(slot), count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .collect();
+        (usage, self.slot_capacity)
+    }
+
+    /// Returns what slots are keyed by, and how many tasks were moved so far when re-slotting
+    ///
+    /// ```bash
+    /// near view cron.testnet get_slot_mode
+    /// ```
+    pub fn get_slot_mode(&self) -> (SlotMode, Option<U64>) {
+        (self.slot_mode, self.reslot_cursor.map(U64::from))
+    }
+
+    /// Returns the current blocks per second estimate, multiplied by 1000,
+    /// along with the sample window it is computed from, as (block height, block timestamp) pairs
+    ///
+    /// ```bash
+    /// near view cron.testnet get_bps_estimate
+    /// ```
+    pub fn get_bps_estimate(&self) -> (U64, Vec<(U64, U64)>) {
+        let mut samples: Vec<(U64, U64)> = self
+            .bps_samples
+            .iter()
+            .map(|(block, ts)| (U64::from(*block), U64::from(*ts)))
+            .collect();
+        samples.sort_by_key(|(block, _)| block.0);
+        (U64::from(self.get_bps()), samples)
+    }
+
+    /// Returns the set of whitelisted agents, paginated
+    ///
+    /// ```bash
+    /// near view cron.testnet get_agent_whitelist '{"from_index": "0", "limit": "10"}'
+    /// ```
+    pub fn get_agent_whitelist(&self, from_index: Option<U64>, limit: Option<U64>) -> Vec<AccountId> {
+        let start = from_index.map(|v| v.0).unwrap_or(0);
+        let limit = limit.map(|v| v.0).unwrap_or(self.agent_whitelist.len());
+        self.agent_whitelist
+            .as_vector()
+            .iter()
+            .skip(start as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Check if an account is allowed to register & execute tasks while whitelist mode is enabled
+    ///
+    /// ```bash
+    /// near view cron.testnet is_agent_whitelisted '{"account_id": "YOUR_AGENT.testnet"}'
+    /// ```
+    pub fn is_agent_whitelisted(&self, account_id: ValidAccountId) -> bool {
+        self.agent_whitelist.contains(account_id.as_ref())
+    }
+}
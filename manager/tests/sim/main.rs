@@ -3,26 +3,29 @@ mod test_utils;
 use crate::test_utils::{
     bootstrap_time_simulation, counter_create_task, find_log_from_outcomes, helper_create_task,
     sim_helper_create_agent_user, sim_helper_init, sim_helper_init_counter,
+    sim_helper_init_staking_pool,
 };
-use manager::{Agent, Task};
+use manager::{ActionKind, Agent, CatchUpPolicy, SlotOrdering, Task, TreasuryInfo};
 use near_sdk::json_types::{Base64VecU8, U128};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::serde_json;
 use near_sdk::serde_json::{json, Value};
 use near_sdk_sim::hash::CryptoHash;
 use near_sdk_sim::transaction::{ExecutionStatus, SignedTransaction};
-use near_sdk_sim::DEFAULT_GAS;
+use near_sdk_sim::{to_yocto, ExecutionResult, UserAccount, DEFAULT_GAS};
 
 // Load in contract bytes at runtime
 near_sdk_sim::lazy_static_include::lazy_static_include_bytes! {
     pub CRON_MANAGER_WASM_BYTES => "../target/wasm32-unknown-unknown/release/manager.wasm",
     pub COUNTER_WASM_BYTES => "../target/wasm32-unknown-unknown/release/rust_counter_tutorial.wasm",
+    pub STAKING_POOL_WASM_BYTES => "../target/wasm32-unknown-unknown/release/mock_staking_pool.wasm",
 }
 
 const MANAGER_ID: &str = "manager.sim";
 const COUNTER_ID: &str = "counter.sim";
 const AGENT_ID: &str = "agent.sim";
 const USER_ID: &str = "user.sim";
+const STAKING_POOL_ID: &str = "staking.sim";
 const NEW_NAME_ID: &str = "newname.sim";
 const TASK_BASE64: &str = "QgpuCtvr2ZRq87F8FG9qKaiKA400LXBOut5WohwCTxI=";
 const AGENT_REGISTRATION_COST: u128 = 2_090_000_000_000_000_000_000;
@@ -109,6 +112,10 @@ fn simulate_many_tasks() {
         success_vec,
         "Should find one particular task hash at slot 120"
     );
+    let slot_ordering: SlotOrdering = root_runtime
+        .view_method_call("cron.root", "get_slot_ordering", "{}".as_bytes())
+        .unwrap_json();
+    assert_eq!(slot_ordering, SlotOrdering::Priority);
 
     // Check that the counter really did update
     let get_counter_view_res = root_runtime
@@ -335,7 +342,7 @@ fn simulate_basic_task_checks() {
     let counter = sim_helper_init_counter(&root);
     helper_create_task(&cron, &counter);
 
-    // Nonexistent task fails.
+    // Malformed hash fails.
     let mut task_view_result = root.view(
         cron.account_id(),
         "get_task",
@@ -345,6 +352,23 @@ fn simulate_basic_task_checks() {
         .to_string()
         .into_bytes(),
     );
+    assert!(
+        task_view_result.is_err(),
+        "Expected malformed task hash to throw error."
+    );
+    let error_message = task_view_result.unwrap_err().to_string();
+    assert!(error_message.contains("Task hash must be 32 bytes"));
+
+    // Nonexistent task fails.
+    task_view_result = root.view(
+        cron.account_id(),
+        "get_task",
+        &json!({
+            "task_hash": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+        })
+        .to_string()
+        .into_bytes(),
+    );
     assert!(
         task_view_result.is_err(),
         "Expected nonexistent task to throw error."
@@ -364,17 +388,42 @@ fn simulate_basic_task_checks() {
         "Expected to find hash of task just added."
     );
     let returned_task: Task = task_view_result.unwrap_json();
+    assert!(returned_task.storage_deposit.0 > 0, "Task storage should be paid for");
 
     let expected_task = Task {
         owner_id: COUNTER_ID.to_string(),
         contract_id: COUNTER_ID.to_string(),
         function_id: "increment".to_string(),
+        kind: ActionKind::FunctionCall,
         cadence: "0   30   9,12,15     1,15       May-Aug  Mon,Wed,Fri  2018/2".to_string(),
         recurring: true,
-        total_deposit: U128::from(2_600_000_024_000_000_000_000u128),
+        total_deposit: U128::from(
+            2_600_000_024_000_000_000_000u128 - returned_task.storage_deposit.0,
+        ),
+        storage_deposit: returned_task.storage_deposit.clone(),
+        storage_prepaid: false,
+        credit_cap: None,
+        credit_used: U128::from(0),
+        sponsor_id: None,
+        approved_sponsor_id: None,
+        owner_deposit: U128::from(0),
+        balance_notify: None,
+        on_result: None,
         deposit: U128::from(12000000000000),
         gas: 3000000000000,
         arguments: Base64VecU8::from(vec![]),
+        actions: vec![],
+        tip: U128::from(0),
+        created_at: returned_task.created_at,
+        jitter: None,
+        catch_up: CatchUpPolicy::Once,
+        catch_up_remaining: 0,
+        skipped_runs: 0,
+        executions: 0,
+        next_ts: returned_task.next_ts.clone(),
+        next_slot: returned_task.next_slot.clone(),
+        yield_checkpoint: U128::from(0),
+        accrued_yield: U128::from(0),
     };
     assert_eq!(
         expected_task, returned_task,
@@ -593,3 +642,298 @@ fn simulate_task_creation_agent_usage() {
         .expect("Error withdrawing task balance");
     find_log_from_outcomes(&root_runtime, &"Withdrawal of".to_string());
 }
+
+/// Manager stakes idle funds above the liquidity reserve, then unstakes & withdraws them.
+#[test]
+fn simulate_staking_tick() {
+    let (root, cron) = sim_helper_init();
+    let pool = sim_helper_init_staking_pool(&root);
+
+    // Only treasury funds are staked, keep 10% of them liquid
+    root.call(
+        cron.account_id(),
+        "deposit_treasury",
+        &json!({}).to_string().into_bytes(),
+        DEFAULT_GAS,
+        to_yocto("100"),
+    )
+    .assert_success();
+    cron.call(
+        cron.account_id(),
+        "update_settings",
+        &json!({
+            "staking_pool_id": STAKING_POOL_ID,
+            "liquidity_reserve_ratio": 1_000
+        })
+        .to_string()
+        .into_bytes(),
+        DEFAULT_GAS,
+        0, // attached deposit
+    )
+    .assert_success();
+
+    let tick = || {
+        // Tick cannot be triggered too soon
+        assert!(
+            root.borrow_runtime_mut().produce_blocks(20).is_ok(),
+            "Couldn't produce blocks"
+        );
+        root.call(
+            cron.account_id(),
+            "tick",
+            &json!({}).to_string().into_bytes(),
+            DEFAULT_GAS,
+            0, // attached deposit
+        )
+        .assert_success();
+    };
+    let staked_balance = || -> U128 {
+        root.view(
+            pool.account_id(),
+            "get_account_staked_balance",
+            &json!({ "account_id": MANAGER_ID }).to_string().into_bytes(),
+        )
+        .unwrap_json()
+    };
+    let treasury = || -> TreasuryInfo {
+        root.view(cron.account_id(), "get_treasury", &[])
+            .unwrap_json()
+    };
+
+    // Stake treasury funds above the reserve
+    tick();
+    let staked = staked_balance();
+    assert_eq!(staked.0, to_yocto("90"));
+    assert_eq!(treasury().staked_balance, staked);
+
+    // Require all funds liquid, unstake everything
+    cron.call(
+        cron.account_id(),
+        "update_settings",
+        &json!({ "liquidity_reserve_ratio": 10_000 })
+            .to_string()
+            .into_bytes(),
+        DEFAULT_GAS,
+        0, // attached deposit
+    )
+    .assert_success();
+    tick();
+    assert_eq!(staked_balance().0, 0);
+    let info = treasury();
+    assert_eq!(info.staked_balance.0, 0);
+    assert_eq!(info.unstaked_balance, staked);
+
+    // Withdraw unstaked funds back to the manager, the blocks produced by `tick` span the unstake delay
+    tick();
+    let info = treasury();
+    assert_eq!(info.unstaked_balance.0, 0);
+    assert_eq!(info.tick_balance.0, to_yocto("100"));
+    let pool_unstaked: U128 = root
+        .view(
+            pool.account_id(),
+            "get_account_unstaked_balance",
+            &json!({ "account_id": MANAGER_ID }).to_string().into_bytes(),
+        )
+        .unwrap_json();
+    assert_eq!(pool_unstaked.0, 0);
+}
+
+/// Staking rewards are shared between tasks, in proportion to their deposits.
+#[test]
+fn simulate_staking_yield() {
+    let (root, cron) = sim_helper_init();
+    let counter = sim_helper_init_counter(&root);
+    let pool = sim_helper_init_staking_pool(&root);
+    helper_create_task(&cron, &counter);
+
+    root.call(
+        cron.account_id(),
+        "deposit_treasury",
+        &json!({}).to_string().into_bytes(),
+        DEFAULT_GAS,
+        to_yocto("100"),
+    )
+    .assert_success();
+    cron.call(
+        cron.account_id(),
+        "update_settings",
+        &json!({
+            "staking_pool_id": STAKING_POOL_ID,
+            "liquidity_reserve_ratio": 1_000,
+            "yield_distribution": "Tasks"
+        })
+        .to_string()
+        .into_bytes(),
+        DEFAULT_GAS,
+        0, // attached deposit
+    )
+    .assert_success();
+
+    let tick = || {
+        // Tick cannot be triggered too soon
+        assert!(
+            root.borrow_runtime_mut().produce_blocks(20).is_ok(),
+            "Couldn't produce blocks"
+        );
+        root.call(
+            cron.account_id(),
+            "tick",
+            &json!({}).to_string().into_bytes(),
+            DEFAULT_GAS,
+            0, // attached deposit
+        )
+        .assert_success();
+    };
+
+    // Stake funds, then the pool pays out 1 Ⓝ of rewards
+    tick();
+    root.call(
+        pool.account_id(),
+        "add_reward",
+        &json!({ "account_id": MANAGER_ID }).to_string().into_bytes(),
+        DEFAULT_GAS,
+        to_yocto("1"),
+    )
+    .assert_success();
+    tick();
+
+    let treasury: TreasuryInfo = root
+        .view(cron.account_id(), "get_treasury", &[])
+        .unwrap_json();
+    assert_eq!(treasury.total_yield.0, to_yocto("1"));
+
+    // Only one task, so it earns all of the yield, minus rounding
+    let task_yield: U128 = root
+        .view(
+            cron.account_id(),
+            "get_task_yield",
+            &json!({ "task_hash": TASK_BASE64 }).to_string().into_bytes(),
+        )
+        .unwrap_json();
+    assert!(task_yield.0 <= to_yocto("1"));
+    assert!(task_yield.0 > to_yocto("0.999"));
+}
+
+/// Gas used by `create_task` & `proxy_call` as a single slot fills up with 10, 100 & 1000 tasks.
+/// Pushing or popping a task hash doesn't read or rewrite the rest of the slot.
+///
+/// cargo test --test sim simulate_slot_gas_benchmark -- --nocapture
+#[test]
+fn simulate_slot_gas_benchmark() {
+    let results: Vec<(u64, u64, u64)> = [10, 100, 1000]
+        .iter()
+        .map(|size| {
+            let (create_gas, proxy_gas) = bench_slot_size(*size);
+            (*size, create_gas, proxy_gas)
+        })
+        .collect();
+    for (size, create_gas, proxy_gas) in results.iter() {
+        println!(
+            "Slot of {} tasks: create_task {} gas, proxy_call {} gas",
+            size, create_gas, proxy_gas
+        );
+    }
+
+    // Flat gas, only the longer function names of later tasks add a little
+    let (_, create_small, proxy_small) = results[0];
+    for (size, create_gas, proxy_gas) in results.iter().skip(1) {
+        assert!(
+            *create_gas < create_small * 11 / 10,
+            "create_task gas grows with a slot of {} tasks",
+            size
+        );
+        assert!(
+            *proxy_gas < proxy_small * 11 / 10,
+            "proxy_call gas grows with a slot of {} tasks",
+            size
+        );
+    }
+}
+
+/// Fills one slot with `size` tasks, returns the gas burnt by the last `create_task`
+/// and by the `proxy_call` executing the first task of the slot
+fn bench_slot_size(size: u64) -> (u64, u64) {
+    let (_, root, agent, counter, cron) = bootstrap_time_simulation();
+    let total_gas_burnt = |res: &ExecutionResult| -> u64 {
+        res.promise_results()
+            .iter()
+            .flatten()
+            .map(|r| r.gas_burnt())
+            .sum()
+    };
+
+    // A wide slot, so every task created below lands in the same one
+    cron.call(
+        cron.account_id(),
+        "update_settings",
+        &json!({ "slot_granularity": 10_000 }).to_string().into_bytes(),
+        DEFAULT_GAS,
+        0, // attached deposit
+    )
+    .assert_success();
+    cron.call(
+        cron.account_id(),
+        "reslot_tasks",
+        &json!({}).to_string().into_bytes(),
+        DEFAULT_GAS,
+        0, // attached deposit
+    )
+    .assert_success();
+    agent
+        .call(
+            cron.account_id(),
+            "register_agent",
+            &json!({}).to_string().into_bytes(),
+            DEFAULT_GAS,
+            AGENT_REGISTRATION_COST,
+        )
+        .assert_success();
+
+    // Task hashes need to differ, the called function doesn't have to exist
+    let mut create_gas = 0;
+    for i in 0..size {
+        let res = counter.call(
+            cron.account_id(),
+            "create_task",
+            &json!({
+                "contract_id": counter.account_id,
+                "function_id": format!("increment_{}", i),
+                "cadence": "0 0 * * * *",
+                "recurring": false,
+                "deposit": "0",
+                "gas": 2_400_000_000_000u64,
+            })
+            .to_string()
+            .into_bytes(),
+            DEFAULT_GAS,
+            to_yocto("0.01"),
+        );
+        res.assert_success();
+        create_gas = total_gas_burnt(&res);
+    }
+
+    let slot_usage = |cron: &UserAccount| -> (U128, u64) {
+        let (usage, _): (Vec<(U128, u64)>, u64) = root
+            .view(
+                cron.account_id(),
+                "get_slot_usage",
+                &json!({ "limit": 1 }).to_string().into_bytes(),
+            )
+            .unwrap_json();
+        usage[0].clone()
+    };
+    let (slot, count) = slot_usage(&cron);
+    assert_eq!(count, size, "Tasks should share a single slot");
+
+    {
+        let mut root_runtime = root.borrow_runtime_mut();
+        let blocks = slot.0 as u64 - root_runtime.cur_block.block_height;
+        assert!(root_runtime.produce_blocks(blocks).is_ok(), "Couldn't produce blocks");
+    }
+
+    // The task call itself fails, only the manager's gas matters here
+    let res = agent.call(cron.account_id(), "proxy_call", &[], DEFAULT_GAS, 0);
+    assert_eq!(slot_usage(&cron).1, size - 1, "Task should be executed");
+
+    (create_gas, total_gas_burnt(&res))
+}
@@ -1,6 +1,6 @@
 use crate::{
     TaskBase64Hash, AGENT_ID, COUNTER_ID, COUNTER_WASM_BYTES, CRON_MANAGER_WASM_BYTES, MANAGER_ID,
-    USER_ID,
+    STAKING_POOL_ID, STAKING_POOL_WASM_BYTES, USER_ID,
 };
 use near_primitives_core::account::Account as PrimitiveAccount;
 use near_sdk::json_types::Base64VecU8;
@@ -74,6 +74,24 @@ pub(crate) fn sim_helper_init_counter(root_account: &UserAccount) -> UserAccount
     counter
 }
 
+pub(crate) fn sim_helper_init_staking_pool(root_account: &UserAccount) -> UserAccount {
+    // Deploy mock staking pool and call "new" method
+    let pool = root_account.deploy(
+        &STAKING_POOL_WASM_BYTES,
+        STAKING_POOL_ID.into(),
+        STORAGE_AMOUNT,
+    );
+    pool.call(
+        pool.account_id(),
+        "new",
+        &[],
+        DEFAULT_GAS,
+        0, // attached deposit
+    )
+    .assert_success();
+    pool
+}
+
 pub(crate) fn counter_create_task(
     counter: &UserAccount,
     cron: AccountId,
@@ -94,7 +112,7 @@ pub(crate) fn counter_create_task(
         .to_string()
         .into_bytes(),
         DEFAULT_GAS,
-        120480000000000000000000, // deposit (0.120000000002 Ⓝ)
+        130480000000000000000000, // deposit (0.13048 Ⓝ), including task storage
     )
 }
 